@@ -0,0 +1,248 @@
+#![allow(clippy::needless_return)]
+
+use glam::Vec3;
+
+use crate::structures::{BoundingBox, EPSILON, Intersection, Traceable};
+
+const SAH_BUCKETS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1f32;
+const INTERSECTION_COST: f32 = 1f32;
+
+/// Bounding volume hierarchy over a scene's objects, built with a binned surface area heuristic.
+/// The hierarchy only stores indices, so it must be queried with the same object list it was
+/// built from.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+struct BvhNode {
+    bounds: BoundingBox,
+    // For leaves, the offset of the first primitive in `indices`. For interior nodes, the index
+    // of the left child; the right child always follows it.
+    offset: usize,
+    count: usize,
+}
+
+impl BvhNode {
+    fn is_leaf(&self) -> bool {
+        return self.count > 0;
+    }
+}
+
+struct Primitive {
+    index: usize,
+    bounds: BoundingBox,
+    centroid: Vec3,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    count: usize,
+    bounds: BoundingBox,
+}
+
+impl Bvh {
+    pub fn build(objects: &[Box<dyn Traceable>]) -> Bvh {
+        let mut primitives: Vec<Primitive> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                // Padding keeps hits that land a rounding error outside of the exact bounds.
+                let bounds = object.bounding_box().padded(EPSILON);
+                Primitive {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                }
+            })
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * primitives.len().max(1)),
+            indices: Vec::with_capacity(primitives.len()),
+        };
+        bvh.nodes.push(BvhNode {
+            bounds: BoundingBox::EMPTY,
+            offset: 0,
+            count: 0,
+        });
+        if !primitives.is_empty() {
+            bvh.build_node(0, &mut primitives);
+        }
+        return bvh;
+    }
+
+    fn build_node(&mut self, node_index: usize, primitives: &mut [Primitive]) {
+        let bounds = primitives
+            .iter()
+            .fold(BoundingBox::EMPTY, |bounds, primitive| {
+                bounds.union(&primitive.bounds)
+            });
+        self.nodes[node_index].bounds = bounds;
+
+        match self.split(primitives, &bounds) {
+            Some(mid) => {
+                let left = self.nodes.len();
+                for _ in 0..2 {
+                    self.nodes.push(BvhNode {
+                        bounds: BoundingBox::EMPTY,
+                        offset: 0,
+                        count: 0,
+                    });
+                }
+                self.nodes[node_index].offset = left;
+                let (left_primitives, right_primitives) = primitives.split_at_mut(mid);
+                self.build_node(left, left_primitives);
+                self.build_node(left + 1, right_primitives);
+            }
+            None => {
+                self.nodes[node_index].offset = self.indices.len();
+                self.nodes[node_index].count = primitives.len();
+                self.indices
+                    .extend(primitives.iter().map(|primitive| primitive.index));
+            }
+        }
+    }
+
+    /// Partitions `primitives` along the cheapest SAH bucket boundary and returns the split
+    /// position, or `None` when a leaf is cheaper than any split.
+    fn split(&self, primitives: &mut [Primitive], bounds: &BoundingBox) -> Option<usize> {
+        if primitives.len() <= 1 {
+            return None;
+        }
+
+        let centroid_bounds = primitives
+            .iter()
+            .fold(BoundingBox::EMPTY, |bounds, primitive| {
+                bounds.grow(primitive.centroid)
+            });
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = extent.max_position();
+        if extent[axis] <= 0f32 {
+            // Every centroid is in the same spot, so no split can separate them.
+            return None;
+        }
+
+        let bucket_of = |centroid: Vec3| -> usize {
+            let relative = (centroid[axis] - centroid_bounds.min[axis]) / extent[axis];
+            return ((relative * SAH_BUCKETS as f32) as usize).min(SAH_BUCKETS - 1);
+        };
+
+        let mut buckets = [Bucket {
+            count: 0,
+            bounds: BoundingBox::EMPTY,
+        }; SAH_BUCKETS];
+        for primitive in primitives.iter() {
+            let bucket = &mut buckets[bucket_of(primitive.centroid)];
+            bucket.count += 1;
+            bucket.bounds = bucket.bounds.union(&primitive.bounds);
+        }
+
+        let parent_area = bounds.surface_area();
+        let mut best_cost = f32::INFINITY;
+        let mut best_bucket = 0;
+        for split in 0..SAH_BUCKETS - 1 {
+            let (below, above) = buckets.split_at(split + 1);
+            let merge = |buckets: &[Bucket]| {
+                buckets
+                    .iter()
+                    .fold((0, BoundingBox::EMPTY), |(count, bounds), bucket| {
+                        (count + bucket.count, bounds.union(&bucket.bounds))
+                    })
+            };
+            let (below_count, below_bounds) = merge(below);
+            let (above_count, above_bounds) = merge(above);
+            if below_count == 0 || above_count == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (below_count as f32 * below_bounds.surface_area()
+                        + above_count as f32 * above_bounds.surface_area())
+                    / parent_area;
+            if cost < best_cost {
+                best_cost = cost;
+                best_bucket = split;
+            }
+        }
+
+        let leaf_cost = INTERSECTION_COST * primitives.len() as f32;
+        if best_cost == f32::INFINITY
+            || (primitives.len() <= MAX_LEAF_SIZE && best_cost >= leaf_cost)
+        {
+            return None;
+        }
+
+        let mut mid = 0;
+        for i in 0..primitives.len() {
+            if bucket_of(primitives[i].centroid) <= best_bucket {
+                primitives.swap(i, mid);
+                mid += 1;
+            }
+        }
+        return Some(mid);
+    }
+
+    /// Finds the closest intersection nearer than `max_distance`. Ties are resolved in favour of
+    /// the object that comes first in `objects`, matching a linear scan over the same list.
+    pub fn intersect<'a>(
+        &self,
+        objects: &'a [Box<dyn Traceable>],
+        origin: &Vec3,
+        direction: &Vec3,
+        max_distance: f32,
+    ) -> Option<Intersection<'a>> {
+        if self.indices.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = direction.recip();
+        let direction_length = direction.length();
+        let mut closest: Option<(usize, f32, Intersection)> = None;
+        let mut stack: Vec<usize> = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let closest_distance = closest
+                .as_ref()
+                .map_or(max_distance, |(_, distance, _)| *distance);
+            // Box distances are parametric, so convert back and leave some slack for rounding.
+            let max_t = closest_distance / direction_length * (1f32 + EPSILON) + EPSILON;
+            if node.bounds.hit(origin, &inverse_direction, max_t).is_none() {
+                continue;
+            }
+
+            if !node.is_leaf() {
+                stack.push(node.offset + 1);
+                stack.push(node.offset);
+                continue;
+            }
+
+            for &object_index in &self.indices[node.offset..node.offset + node.count] {
+                let Some(intersection) = objects[object_index].intersection(origin, direction)
+                else {
+                    continue;
+                };
+                let distance = (intersection.point - origin).length();
+                // Degenerate hits can produce NaN points, which the linear scan never accepts.
+                if distance.is_nan() || distance >= max_distance {
+                    continue;
+                }
+                let is_closer = match &closest {
+                    Some((closest_index, closest_distance, _)) => {
+                        distance < *closest_distance
+                            || (distance == *closest_distance && object_index < *closest_index)
+                    }
+                    None => true,
+                };
+                if is_closer {
+                    closest = Some((object_index, distance, intersection));
+                }
+            }
+        }
+
+        return closest.map(|(_, _, intersection)| intersection);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Problems found while loading a scene. JSON related variants carry the path of the offending
+/// field, e.g. `objects[3].material.albedo`, so that a broken scene file can be fixed without
+/// guessing.
+#[derive(Debug)]
+pub enum SceneError {
+    MissingFile {
+        path: PathBuf,
+        source: io::Error,
+    },
+    InvalidJson {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    MissingField {
+        field: String,
+    },
+    WrongType {
+        field: String,
+        message: String,
+    },
+    InvalidValue {
+        field: String,
+        message: String,
+    },
+    UnknownObject {
+        field: String,
+        name: String,
+    },
+    /// A reference to a named definition, such as a mesh, that the scene does not contain.
+    UndefinedName {
+        field: String,
+        kind: &'static str,
+        name: String,
+    },
+    /// `field` is `None` for meshes that are not referenced from a scene file, such as the ones
+    /// in the default scene.
+    BadMeshPath {
+        field: Option<String>,
+        path: PathBuf,
+        message: String,
+    },
+    BadTexture {
+        field: String,
+        path: PathBuf,
+        message: String,
+    },
+    /// A `.gltf` or `.glb` scene, with `message` naming the offending part of the file.
+    BadGltf {
+        path: PathBuf,
+        message: String,
+    },
+    /// The `.mtl` file named by the `mtllib` statement of a mesh.
+    BadMaterialLibrary {
+        field: String,
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::MissingFile { path, source } => {
+                write!(formatter, "unable to open {}: {source}", path.display())
+            }
+            SceneError::InvalidJson { path, source } => {
+                write!(formatter, "{} is not valid JSON: {source}", path.display())
+            }
+            SceneError::MissingField { field } => {
+                write!(formatter, "{field}: missing required field")
+            }
+            SceneError::WrongType { field, message } => write!(formatter, "{field}: {message}"),
+            SceneError::InvalidValue { field, message } => {
+                write!(formatter, "{field}: {message}")
+            }
+            SceneError::UnknownObject { field, name } => {
+                write!(formatter, "{field}: unknown object kind \"{name}\"")
+            }
+            SceneError::UndefinedName { field, kind, name } => {
+                write!(formatter, "{field}: no {kind} named \"{name}\" is defined")
+            }
+            SceneError::BadMeshPath {
+                field: Some(field),
+                path,
+                message,
+            } => write!(
+                formatter,
+                "{field}: unable to load mesh {}: {message}",
+                path.display()
+            ),
+            SceneError::BadMeshPath {
+                field: None,
+                path,
+                message,
+            } => write!(
+                formatter,
+                "unable to load mesh {}: {message}",
+                path.display()
+            ),
+            SceneError::BadTexture {
+                field,
+                path,
+                message,
+            } => write!(
+                formatter,
+                "{field}: unable to load texture {}: {message}",
+                path.display()
+            ),
+            SceneError::BadGltf { path, message } => {
+                write!(
+                    formatter,
+                    "unable to load glTF {}: {message}",
+                    path.display()
+                )
+            }
+            SceneError::BadMaterialLibrary {
+                field,
+                path,
+                message,
+            } => write!(
+                formatter,
+                "{field}: unable to load material library {}: {message}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::MissingFile { source, .. } => Some(source),
+            SceneError::InvalidJson { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
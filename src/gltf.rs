@@ -0,0 +1,1285 @@
+#![allow(clippy::needless_return)]
+
+use std::{
+    collections::HashMap,
+    f32::consts::FRAC_PI_4,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+use image::Rgb32FImage;
+use serde::Deserialize;
+
+use crate::error::SceneError;
+use crate::init::{SkippedPrimitives, linear_image};
+use crate::structures::{
+    Attenuation, Camera, Color, Emission, Emitters, Instance, Light, LightKind, Material, Mesh,
+    PbrMaterial, Scene, Traceable, Triangle,
+};
+use crate::texture::{ColorInput, ImageTexture, ScalarInput, Texture, TextureFilter, WrapMode};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BINARY_CHUNK: u32 = 0x004E_4942;
+/// Most values an accessor without a buffer view may hold. Nothing bounds them otherwise, and
+/// meshes of all-zero data have no use beyond a placeholder.
+const MAX_ZERO_VALUES: usize = 1 << 22;
+
+/// Whether `path` names a glTF file, by its `.gltf` or `.glb` extension.
+pub fn is_gltf(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    return extension.is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+    });
+}
+
+/// Loads a glTF 2.0 file as a scene: the meshes, cameras and `KHR_lights_punctual` lights of
+/// its default scene, placed by the node hierarchy. Every primitive of a mesh is stored once and
+/// instanced by the nodes that use it. The first camera found becomes the scene's camera.
+pub fn load_gltf(path: &Path) -> Result<Scene, Vec<SceneError>> {
+    let bad_gltf = |message: String| {
+        vec![SceneError::BadGltf {
+            path: path.to_path_buf(),
+            message,
+        }]
+    };
+    let bytes = fs::read(path).map_err(|error| bad_gltf(error.to_string()))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let (scene, skipped) = parse_gltf(&bytes, directory).map_err(bad_gltf)?;
+    skipped.report(path);
+    return Ok(scene);
+}
+
+/// Parses the contents of a `.gltf` or `.glb` file, whose external buffers and images are
+/// relative to `directory`.
+fn parse_gltf(bytes: &[u8], directory: &Path) -> Result<(Scene, SkippedPrimitives), String> {
+    let (json, binary) = match bytes.get(0..4) == Some(&GLB_MAGIC.to_le_bytes()[..]) {
+        true => split_glb(bytes)?,
+        false => (bytes, None),
+    };
+    let document: Document =
+        serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_slice(json))
+            .map_err(|error| format!("{}: {}", error.path(), error.inner()))?;
+    let buffers = document
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(index, buffer)| load_buffer(buffer, index, binary, directory))
+        .collect::<Result<Vec<Vec<u8>>, String>>()?;
+
+    let mut loader = Loader {
+        document: &document,
+        buffers,
+        directory,
+        images: HashMap::new(),
+        skipped: SkippedPrimitives::default(),
+    };
+    let default_material = loader.material(&GltfMaterial::default())?;
+    let materials = document
+        .materials
+        .iter()
+        .map(|material| loader.material(material))
+        .collect::<Result<Vec<Material>, String>>()?;
+    let meshes = document
+        .meshes
+        .iter()
+        .enumerate()
+        .map(|(index, mesh)| loader.mesh(mesh, index, &materials, &default_material))
+        .collect::<Result<Vec<Vec<Arc<Mesh>>>, String>>()?;
+
+    let mut scene = Scene {
+        lights: Vec::new(),
+        objects: Vec::new(),
+        background: None,
+        camera: Camera::default(),
+        bvh: None,
+        emitters: Emitters::default(),
+    };
+    let mut camera_found = false;
+    let roots = match document
+        .scene
+        .or((!document.scenes.is_empty()).then_some(0))
+    {
+        Some(index) => {
+            let root_scene = document
+                .scenes
+                .get(index)
+                .ok_or_else(|| format!("scene: scene {index} does not exist"))?;
+            root_scene.nodes.clone()
+        }
+        // Without scenes, every node that is not a child of another one is a root.
+        None => (0..document.nodes.len())
+            .filter(|index| {
+                !document
+                    .nodes
+                    .iter()
+                    .any(|node| node.children.contains(index))
+            })
+            .collect(),
+    };
+    // Depth first, in the order of the file, so that the first camera is the one found first.
+    let mut stack: Vec<(usize, Mat4)> = roots
+        .iter()
+        .rev()
+        .map(|&index| (index, Mat4::IDENTITY))
+        .collect();
+    let mut visited = 0;
+    while let Some((index, parent)) = stack.pop() {
+        let node = document
+            .nodes
+            .get(index)
+            .ok_or_else(|| format!("node {index} does not exist"))?;
+        visited += 1;
+        if visited > document.nodes.len() {
+            return Err(String::from("nodes: the node hierarchy is not a tree"));
+        }
+        let world = parent * node.local_matrix();
+
+        if let Some(mesh) = node.mesh {
+            let primitives = meshes
+                .get(mesh)
+                .ok_or_else(|| format!("nodes[{index}].mesh: mesh {mesh} does not exist"))?;
+            for primitive in primitives {
+                let instance = Instance::from_matrix(primitive.clone(), world)
+                    .map_err(|message| format!("nodes[{index}]: the transform {message}"))?;
+                scene.objects.push(Box::new(instance));
+            }
+        }
+        if let Some(camera) = node.camera.filter(|_| !camera_found) {
+            let camera = document
+                .cameras
+                .get(camera)
+                .ok_or_else(|| format!("nodes[{index}].camera: camera {camera} does not exist"))?;
+            match &camera.perspective {
+                Some(perspective) => {
+                    // glTF cameras look down their local -z axis, with +y up.
+                    let position = world.transform_point3(Vec3::ZERO);
+                    scene.camera = Camera {
+                        position,
+                        target: position + world.transform_vector3(Vec3::NEG_Z),
+                        up: world.transform_vector3(Vec3::Y),
+                        fov: perspective.yfov.to_degrees(),
+                        aspect: perspective.aspect_ratio,
+                    };
+                    camera_found = true;
+                }
+                None => eprintln!(
+                    "Warning: skipped the {} camera of node {index}, as only perspective cameras are supported.",
+                    camera.kind
+                ),
+            }
+        }
+        if let Some(NodeLight { light }) = node.extensions.lights_punctual {
+            let lights = &document.extensions.lights_punctual.lights;
+            let light = lights.get(light).ok_or_else(|| {
+                format!(
+                    "nodes[{index}].extensions.KHR_lights_punctual: light {light} does not exist"
+                )
+            })?;
+            scene.lights.push(light.to_light(&world)?);
+        }
+
+        stack.extend(node.children.iter().rev().map(|&child| (child, world)));
+    }
+    return Ok((scene, loader.skipped));
+}
+
+/// Splits a binary `.glb` file into its JSON chunk and its optional binary chunk.
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), String> {
+    let word = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .ok_or_else(|| String::from("the binary container is truncated"))
+    };
+    if word(4)? != 2 {
+        return Err(format!("unsupported binary container version {}", word(4)?));
+    }
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+    while offset < bytes.len() {
+        let length = word(offset)? as usize;
+        let kind = word(offset + 4)?;
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| String::from("the binary container is truncated"))?;
+        match kind {
+            GLB_JSON_CHUNK => json = json.or(Some(chunk)),
+            GLB_BINARY_CHUNK => binary = binary.or(Some(chunk)),
+            // Unknown chunks are to be skipped.
+            _ => {}
+        }
+        offset += 8 + length;
+    }
+    let json = json.ok_or_else(|| String::from("the binary container has no JSON chunk"))?;
+    return Ok((json, binary));
+}
+
+fn load_buffer(
+    buffer: &Buffer,
+    index: usize,
+    binary: Option<&[u8]>,
+    directory: &Path,
+) -> Result<Vec<u8>, String> {
+    let field = format!("buffers[{index}]");
+    let data = match &buffer.uri {
+        Some(uri) => load_uri(uri, directory).map_err(|message| format!("{field}: {message}"))?,
+        None => binary
+            .map(|binary| binary.to_vec())
+            .ok_or_else(|| format!("{field}: a buffer without a uri needs a binary chunk"))?,
+    };
+    if data.len() < buffer.byte_length {
+        return Err(format!(
+            "{field}: expected {} bytes but found {}",
+            buffer.byte_length,
+            data.len()
+        ));
+    }
+    return Ok(data);
+}
+
+/// Reads a `data:` URI, or a file relative to `directory`.
+fn load_uri(uri: &str, directory: &Path) -> Result<Vec<u8>, String> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let (header, payload) = data
+                .split_once(',')
+                .ok_or_else(|| String::from("malformed data URI"))?;
+            if !header.ends_with(";base64") {
+                return Err(String::from("only base64 data URIs are supported"));
+            }
+            return decode_base64(payload);
+        }
+        None => {
+            let path = directory.join(percent_decode(uri));
+            return fs::read(&path).map_err(|error| format!("{}: {error}", path.display()));
+        }
+    }
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for character in text
+        .bytes()
+        .filter(|character| !character.is_ascii_whitespace())
+    {
+        let value = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return Err(format!("invalid base64 character '{}'", character as char)),
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    return Ok(bytes);
+}
+
+/// Undoes the `%20` style escapes that relative URIs use for special characters.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    return String::from_utf8_lossy(&decoded).into_owned();
+}
+
+/// How the pixels of an image are turned into a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ImageChannels {
+    /// sRGB encoded colors.
+    Color,
+    /// Linear data, such as normals.
+    Data,
+    /// One linear channel, spread over all three so that scalar inputs read it alone.
+    Single(usize),
+}
+
+struct Loader<'a> {
+    document: &'a Document,
+    buffers: Vec<Vec<u8>>,
+    directory: &'a Path,
+    /// Decoded images by image, channels and the factor baked into them.
+    images: HashMap<(usize, ImageChannels, [u32; 3]), Arc<Rgb32FImage>>,
+    skipped: SkippedPrimitives,
+}
+
+impl Loader<'_> {
+    /// Converts a metallic-roughness material. Factors that glTF multiplies with a texture are
+    /// baked into the texture's pixels.
+    fn material(&mut self, material: &GltfMaterial) -> Result<Material, String> {
+        let pbr = &material.pbr_metallic_roughness;
+        let base_factor = Vec4::from(pbr.base_color_factor).truncate();
+        let base_color = match &pbr.base_color_texture {
+            Some(info) => {
+                ColorInput::Texture(self.texture(info, ImageChannels::Color, base_factor)?)
+            }
+            None => ColorInput::Constant(Color::from_linear(base_factor)),
+        };
+        // The texture holds roughness in its green channel and metalness in its blue one.
+        let mut scalar = |factor: f32, channel: usize| -> Result<ScalarInput, String> {
+            return match &pbr.metallic_roughness_texture {
+                Some(info) => Ok(ScalarInput::Texture(self.texture(
+                    info,
+                    ImageChannels::Single(channel),
+                    Vec3::splat(factor),
+                )?)),
+                None => Ok(ScalarInput::Constant(factor)),
+            };
+        };
+        let metallic = scalar(pbr.metallic_factor, 2)?;
+        let roughness = scalar(pbr.roughness_factor, 1)?;
+        let normal_map = match &material.normal_texture {
+            Some(info) => Some(self.texture(info, ImageChannels::Data, Vec3::ONE)?),
+            None => None,
+        };
+
+        let extensions = &material.extensions;
+        let emissive_factor = Vec3::from(material.emissive_factor);
+        let strength = emissive_factor.max_element()
+            * extensions
+                .emissive_strength
+                .as_ref()
+                .map_or(1f32, |extension| extension.emissive_strength);
+        let emission = match (strength > 0f32, &material.emissive_texture) {
+            (false, _) => None,
+            (true, Some(info)) => Some(Emission {
+                color: ColorInput::Texture(self.texture(
+                    info,
+                    ImageChannels::Color,
+                    emissive_factor / emissive_factor.max_element(),
+                )?),
+                strength,
+            }),
+            (true, None) => Some(Emission {
+                color: ColorInput::Constant(Color::from_linear(
+                    emissive_factor / emissive_factor.max_element(),
+                )),
+                strength,
+            }),
+        };
+
+        let material = PbrMaterial {
+            base_color,
+            metallic,
+            roughness,
+            ior: ScalarInput::Constant(
+                extensions
+                    .ior
+                    .as_ref()
+                    .map_or(1.5, |extension| extension.ior),
+            ),
+            transmission: ScalarInput::Constant(
+                extensions
+                    .transmission
+                    .as_ref()
+                    .map_or(0f32, |extension| extension.transmission_factor),
+            ),
+            absorption: None,
+            normal_map,
+            bump_map: None,
+            emission,
+        };
+        return Ok(Material::Pbr(material));
+    }
+
+    fn texture(
+        &mut self,
+        info: &TextureInfo,
+        channels: ImageChannels,
+        factor: Vec3,
+    ) -> Result<Texture, String> {
+        let field = format!("textures[{}]", info.index);
+        let texture = self
+            .document
+            .textures
+            .get(info.index)
+            .ok_or_else(|| format!("{field}: texture does not exist"))?;
+        let source = texture
+            .source
+            .ok_or_else(|| format!("{field}: only textures with a source image are supported"))?;
+        let (wrap_s, wrap_t, mag_filter) = match texture.sampler {
+            Some(sampler) => {
+                let sampler =
+                    self.document.samplers.get(sampler).ok_or_else(|| {
+                        format!("{field}.sampler: sampler {sampler} does not exist")
+                    })?;
+                (sampler.wrap_s, sampler.wrap_t, sampler.mag_filter)
+            }
+            None => (default_wrap(), default_wrap(), None),
+        };
+        let key = (source, channels, factor.to_array().map(f32::to_bits));
+        let image = match self.images.get(&key) {
+            Some(image) => image.clone(),
+            None => {
+                let image = Arc::new(self.image(source, channels, factor)?);
+                self.images.insert(key, image.clone());
+                image
+            }
+        };
+        return Ok(Texture::Image(ImageTexture {
+            path: PathBuf::from(format!("images[{source}]")),
+            wrap: [wrap_s, wrap_t].map(|wrap| match wrap {
+                33071 => WrapMode::Clamp,
+                33648 => WrapMode::Mirror,
+                _ => WrapMode::Repeat,
+            }),
+            filter: match mag_filter {
+                Some(9728) => TextureFilter::Nearest,
+                _ => TextureFilter::Bilinear,
+            },
+            scale: Vec2::ONE,
+            image: Some(image),
+        }));
+    }
+
+    fn image(
+        &self,
+        index: usize,
+        channels: ImageChannels,
+        factor: Vec3,
+    ) -> Result<Rgb32FImage, String> {
+        let field = format!("images[{index}]");
+        let image = self
+            .document
+            .images
+            .get(index)
+            .ok_or_else(|| format!("{field}: image does not exist"))?;
+        let bytes = match (&image.uri, image.buffer_view) {
+            (Some(uri), _) => load_uri(uri, self.directory)?,
+            (None, Some(view)) => self.buffer_view(view)?.to_vec(),
+            (None, None) => {
+                return Err(format!("{field}: image has neither a uri nor a bufferView"));
+            }
+        };
+        let image = image::load_from_memory(&bytes).map_err(|error| format!("{field}: {error}"))?;
+        let mut image = match channels {
+            ImageChannels::Color => linear_image(image),
+            _ => image.into_rgb32f(),
+        };
+        for pixel in image.pixels_mut() {
+            let value = match channels {
+                ImageChannels::Single(channel) => Vec3::splat(pixel.0[channel]),
+                _ => Vec3::from(pixel.0),
+            };
+            pixel.0 = (value * factor).to_array();
+        }
+        return Ok(image);
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<&[u8], String> {
+        let field = format!("bufferViews[{index}]");
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| format!("{field}: buffer view does not exist"))?;
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| format!("{field}.buffer: buffer {} does not exist", view.buffer))?;
+        return view
+            .byte_offset
+            .checked_add(view.byte_length)
+            .and_then(|end| buffer.get(view.byte_offset..end))
+            .ok_or_else(|| format!("{field}: extends past the end of its buffer"));
+    }
+
+    /// Reads the elements of an accessor as flat values, `width` per element, undoing the
+    /// normalization of integer components where the accessor asks for it.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), String> {
+        let field = format!("accessors[{index}]");
+        let accessor = self
+            .document
+            .accessors
+            .get(index)
+            .ok_or_else(|| format!("{field}: accessor does not exist"))?;
+        if accessor.sparse.is_some() {
+            return Err(format!("{field}: sparse accessors are not supported"));
+        }
+        let width = match accessor.kind.as_str() {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" | "MAT2" => 4,
+            "MAT3" => 9,
+            "MAT4" => 16,
+            kind => return Err(format!("{field}.type: unknown type {kind}")),
+        };
+        let size = match accessor.component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            kind => {
+                return Err(format!(
+                    "{field}.componentType: unknown component type {kind}"
+                ));
+            }
+        };
+        let length = accessor
+            .count
+            .checked_mul(width)
+            .ok_or_else(|| format!("{field}.count: too many elements"))?;
+        let Some(view_index) = accessor.buffer_view else {
+            // Accessors without a buffer view are all zeros.
+            if length > MAX_ZERO_VALUES {
+                return Err(format!(
+                    "{field}.count: too many elements without a buffer view"
+                ));
+            }
+            return Ok((vec![0f64; length], width));
+        };
+        let view = self.buffer_view(view_index)?;
+        let stride = self.document.buffer_views[view_index]
+            .byte_stride
+            .unwrap_or(width * size);
+        // The last element has to end within the view, which also bounds the allocation below.
+        let end = match accessor.count {
+            0 => Some(accessor.byte_offset),
+            count => (count - 1)
+                .checked_mul(stride)
+                .and_then(|last| last.checked_add(width * size))
+                .and_then(|end| end.checked_add(accessor.byte_offset)),
+        };
+        if end.is_none_or(|end| end > view.len()) {
+            return Err(format!("{field}: extends past the end of its buffer view"));
+        }
+
+        let mut values = Vec::with_capacity(length);
+        for element in 0..accessor.count {
+            for component in 0..width {
+                let offset = accessor.byte_offset + element * stride + component * size;
+                let bytes = &view[offset..offset + size];
+                let value = match accessor.component_type {
+                    5120 => bytes[0] as i8 as f64,
+                    5121 => bytes[0] as f64,
+                    5122 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5123 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    5125 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                };
+                let value = match (accessor.normalized, accessor.component_type) {
+                    (true, 5120) => (value / 127f64).max(-1f64),
+                    (true, 5121) => value / 255f64,
+                    (true, 5122) => (value / 32767f64).max(-1f64),
+                    (true, 5123) => value / 65535f64,
+                    _ => value,
+                };
+                values.push(value);
+            }
+        }
+        return Ok((values, width));
+    }
+
+    fn attribute<const N: usize>(
+        &self,
+        primitive: &MeshPrimitive,
+        name: &str,
+        field: &str,
+    ) -> Result<Option<Vec<[f32; N]>>, String> {
+        let Some(&index) = primitive.attributes.get(name) else {
+            return Ok(None);
+        };
+        let (values, width) = self.accessor(index)?;
+        if width != N {
+            return Err(format!(
+                "{field}.attributes.{name}: expected {N} components per element"
+            ));
+        }
+        let elements = values
+            .chunks_exact(N)
+            .map(|element| std::array::from_fn(|component| element[component] as f32))
+            .collect();
+        return Ok(Some(elements));
+    }
+
+    /// Builds one shared mesh per primitive, so that each has a single material.
+    fn mesh(
+        &mut self,
+        mesh: &GltfMesh,
+        index: usize,
+        materials: &[Material],
+        default_material: &Material,
+    ) -> Result<Vec<Arc<Mesh>>, String> {
+        let mut meshes = Vec::new();
+        for (primitive_index, primitive) in mesh.primitives.iter().enumerate() {
+            let field = format!("meshes[{index}].primitives[{primitive_index}]");
+            let positions: Vec<[f32; 3]> = self
+                .attribute(primitive, "POSITION", &field)?
+                .ok_or_else(|| format!("{field}.attributes: missing POSITION"))?;
+            let normals: Option<Vec<[f32; 3]>> = self.attribute(primitive, "NORMAL", &field)?;
+            let uvs: Option<Vec<[f32; 2]>> = self.attribute(primitive, "TEXCOORD_0", &field)?;
+            let indices: Vec<usize> = match primitive.indices {
+                Some(indices) => self
+                    .accessor(indices)?
+                    .0
+                    .into_iter()
+                    .map(|index| index as usize)
+                    .collect(),
+                None => (0..positions.len()).collect(),
+            };
+            let material = match primitive.material {
+                Some(material) => materials.get(material).ok_or_else(|| {
+                    format!("{field}.material: material {material} does not exist")
+                })?,
+                None => default_material,
+            };
+
+            let count = indices.len();
+            let corners: Vec<[usize; 3]> = match primitive.mode {
+                4 => indices
+                    .chunks_exact(3)
+                    .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                    .collect(),
+                // Every other triangle of a strip is flipped to keep the winding.
+                5 => (0..count.saturating_sub(2))
+                    .map(|first| match first % 2 {
+                        0 => [indices[first], indices[first + 1], indices[first + 2]],
+                        _ => [indices[first + 1], indices[first], indices[first + 2]],
+                    })
+                    .collect(),
+                6 => (1..count.saturating_sub(1))
+                    .map(|first| [indices[first], indices[first + 1], indices[0]])
+                    .collect(),
+                mode => {
+                    match mode {
+                        0 => self.skipped.points += count,
+                        1 => self.skipped.lines += count / 2,
+                        2 => self.skipped.lines += count,
+                        3 => self.skipped.lines += count.saturating_sub(1),
+                        _ => return Err(format!("{field}.mode: unknown mode {mode}")),
+                    }
+                    continue;
+                }
+            };
+
+            let mut triangles: Vec<Box<dyn Traceable>> = Vec::with_capacity(corners.len());
+            for corner in corners {
+                if corner.iter().any(|&index| index >= positions.len()) {
+                    return Err(format!("{field}.indices: index out of range"));
+                }
+                let [v0, v1, v2] = corner.map(|index| Vec3::from(positions[index]));
+                triangles.push(Box::new(Triangle {
+                    v0,
+                    v1,
+                    v2,
+                    normals: normals.as_ref().and_then(|normals| {
+                        let [n0, n1, n2] = corner.map(|index| normals.get(index).copied());
+                        Some([n0?, n1?, n2?].map(|normal| Vec3::from(normal).normalize_or_zero()))
+                    }),
+                    // glTF measures v from the top of the image, ferox from the bottom.
+                    uvs: uvs.as_ref().and_then(|uvs| {
+                        let [t0, t1, t2] = corner.map(|index| uvs.get(index).copied());
+                        Some([t0?, t1?, t2?].map(|[u, v]| Vec2::new(u, 1f32 - v)))
+                    }),
+                    material: material.clone(),
+                }));
+            }
+            if let Some(mesh) = Mesh::new(triangles) {
+                meshes.push(Arc::new(mesh));
+            }
+        }
+        return Ok(meshes);
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<GltfScene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<GltfMesh>,
+    #[serde(default)]
+    materials: Vec<GltfMaterial>,
+    #[serde(default)]
+    textures: Vec<GltfTexture>,
+    #[serde(default)]
+    images: Vec<GltfImage>,
+    #[serde(default)]
+    samplers: Vec<Sampler>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    cameras: Vec<GltfCamera>,
+    #[serde(default)]
+    extensions: DocumentExtensions,
+}
+
+#[derive(Deserialize, Default)]
+struct DocumentExtensions {
+    #[serde(rename = "KHR_lights_punctual", default)]
+    lights_punctual: LightsPunctual,
+}
+
+#[derive(Deserialize, Default)]
+struct LightsPunctual {
+    #[serde(default)]
+    lights: Vec<PunctualLight>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PunctualLight {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "default_light_color")]
+    color: [f32; 3],
+    #[serde(default = "default_light_intensity")]
+    intensity: f32,
+    range: Option<f32>,
+    spot: Option<Spot>,
+}
+
+fn default_light_color() -> [f32; 3] {
+    return [1f32; 3];
+}
+
+fn default_light_intensity() -> f32 {
+    return 1f32;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Spot {
+    #[serde(default)]
+    inner_cone_angle: f32,
+    #[serde(default = "default_outer_cone_angle")]
+    outer_cone_angle: f32,
+}
+
+fn default_outer_cone_angle() -> f32 {
+    return FRAC_PI_4;
+}
+
+impl PunctualLight {
+    /// Point and spot lights fall off with the inverse square of the distance, windowed to zero
+    /// at `range` when there is one. Intensities carry over unchanged.
+    fn to_light(&self, world: &Mat4) -> Result<Light, String> {
+        let position = world.transform_point3(Vec3::ZERO);
+        // Lights shine down their local -z axis.
+        let direction = world.transform_vector3(Vec3::NEG_Z).normalize();
+        let kind = match self.kind.as_str() {
+            "point" => LightKind::Point { position },
+            "directional" => LightKind::Directional { direction },
+            "spot" => {
+                let (inner, outer) = self.spot.as_ref().map_or((0f32, FRAC_PI_4), |spot| {
+                    (spot.inner_cone_angle, spot.outer_cone_angle)
+                });
+                LightKind::Spot {
+                    position,
+                    direction,
+                    cone_angle: outer.to_degrees(),
+                    falloff: (outer - inner).to_degrees(),
+                }
+            }
+            kind => return Err(format!("KHR_lights_punctual: unknown light type {kind}")),
+        };
+        let attenuation = match (&kind, self.range) {
+            (LightKind::Directional { .. }, _) => Attenuation::None,
+            (_, Some(range)) => {
+                let attenuation = Attenuation::Smooth { range };
+                attenuation
+                    .validate()
+                    .map_err(|message| format!("KHR_lights_punctual: {message}"))?;
+                attenuation
+            }
+            (_, None) => Attenuation::InverseSquare,
+        };
+        return Ok(Light {
+            kind,
+            intensity: self.intensity,
+            color: Color::from_linear(Vec3::from(self.color)),
+            temperature: None,
+            attenuation,
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct GltfScene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    children: Vec<usize>,
+    /// Column-major, replacing `translation`, `rotation` and `scale` when present.
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    /// Quaternion as x, y, z and w.
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+    mesh: Option<usize>,
+    camera: Option<usize>,
+    #[serde(default)]
+    extensions: NodeExtensions,
+}
+
+impl Node {
+    fn local_matrix(&self) -> Mat4 {
+        if let Some(matrix) = self.matrix {
+            return Mat4::from_cols_array(&matrix);
+        }
+        return Mat4::from_scale_rotation_translation(
+            self.scale.map_or(Vec3::ONE, Vec3::from),
+            self.rotation.map_or(Quat::IDENTITY, Quat::from_array),
+            self.translation.map_or(Vec3::ZERO, Vec3::from),
+        );
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct NodeExtensions {
+    #[serde(rename = "KHR_lights_punctual")]
+    lights_punctual: Option<NodeLight>,
+}
+
+#[derive(Deserialize)]
+struct NodeLight {
+    light: usize,
+}
+
+#[derive(Deserialize)]
+struct GltfMesh {
+    primitives: Vec<MeshPrimitive>,
+}
+
+#[derive(Deserialize)]
+struct MeshPrimitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    #[serde(default = "default_primitive_mode")]
+    mode: u32,
+}
+
+fn default_primitive_mode() -> u32 {
+    // Triangles.
+    return 4;
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GltfMaterial {
+    #[serde(default)]
+    pbr_metallic_roughness: PbrMetallicRoughness,
+    normal_texture: Option<TextureInfo>,
+    emissive_texture: Option<TextureInfo>,
+    #[serde(default)]
+    emissive_factor: [f32; 3],
+    #[serde(default)]
+    extensions: MaterialExtensions,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PbrMetallicRoughness {
+    base_color_factor: [f32; 4],
+    base_color_texture: Option<TextureInfo>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+impl Default for PbrMetallicRoughness {
+    fn default() -> Self {
+        PbrMetallicRoughness {
+            base_color_factor: [1f32; 4],
+            base_color_texture: None,
+            metallic_factor: 1f32,
+            roughness_factor: 1f32,
+            metallic_roughness_texture: None,
+        }
+    }
+}
+
+/// Only `TEXCOORD_0` is read, so the `texCoord` of a texture reference is ignored.
+#[derive(Deserialize)]
+struct TextureInfo {
+    index: usize,
+}
+
+#[derive(Deserialize, Default)]
+struct MaterialExtensions {
+    #[serde(rename = "KHR_materials_ior")]
+    ior: Option<IorExtension>,
+    #[serde(rename = "KHR_materials_transmission")]
+    transmission: Option<TransmissionExtension>,
+    #[serde(rename = "KHR_materials_emissive_strength")]
+    emissive_strength: Option<EmissiveStrengthExtension>,
+}
+
+#[derive(Deserialize)]
+struct IorExtension {
+    #[serde(default = "default_ior")]
+    ior: f32,
+}
+
+fn default_ior() -> f32 {
+    return 1.5;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransmissionExtension {
+    #[serde(default)]
+    transmission_factor: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EmissiveStrengthExtension {
+    #[serde(default = "default_light_intensity")]
+    emissive_strength: f32,
+}
+
+#[derive(Deserialize)]
+struct GltfTexture {
+    source: Option<usize>,
+    sampler: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfImage {
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sampler {
+    mag_filter: Option<u32>,
+    #[serde(default = "default_wrap")]
+    wrap_s: u32,
+    #[serde(default = "default_wrap")]
+    wrap_t: u32,
+}
+
+fn default_wrap() -> u32 {
+    // Repeat.
+    return 10497;
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(Deserialize)]
+struct GltfCamera {
+    #[serde(rename = "type")]
+    kind: String,
+    perspective: Option<Perspective>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Perspective {
+    yfov: f32,
+    aspect_ratio: Option<f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs a document and its binary buffer into a `.glb` container.
+    fn glb(document: &serde_json::Value, buffer: &[u8]) -> Vec<u8> {
+        let mut json = document.to_string().into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut buffer = buffer.to_vec();
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+        let mut bytes = Vec::new();
+        for word in [
+            GLB_MAGIC,
+            2,
+            (12 + 8 + json.len() + 8 + buffer.len()) as u32,
+        ] {
+            bytes.extend(word.to_le_bytes());
+        }
+        for (kind, chunk) in [(GLB_JSON_CHUNK, json), (GLB_BINARY_CHUNK, buffer)] {
+            bytes.extend((chunk.len() as u32).to_le_bytes());
+            bytes.extend(kind.to_le_bytes());
+            bytes.extend(chunk);
+        }
+        return bytes;
+    }
+
+    #[test]
+    fn node_hierarchies_place_shared_meshes_cameras_and_lights() {
+        let positions = [[0f32, 0f32, 0f32], [1f32, 0f32, 0f32], [0f32, 1f32, 0f32]];
+        let buffer: Vec<u8> = positions
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let document = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0, 3] }],
+            "nodes": [
+                { "translation": [0, 0, -5], "children": [1, 2] },
+                { "mesh": 0 },
+                { "mesh": 0, "translation": [3, 0, 0], "scale": [2, 2, 2] },
+                {
+                    "camera": 0,
+                    "translation": [0, 0, 1],
+                    "extensions": { "KHR_lights_punctual": { "light": 0 } }
+                }
+            ],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }],
+            "bufferViews": [{ "buffer": 0, "byteLength": buffer.len() }],
+            "buffers": [{ "byteLength": buffer.len() }],
+            "cameras": [{ "type": "perspective", "perspective": { "yfov": 1, "aspectRatio": 1.5, "znear": 0.1 } }],
+            "extensions": {
+                "KHR_lights_punctual": { "lights": [{ "type": "point", "range": 10 }] }
+            }
+        });
+
+        let (scene, skipped) = parse_gltf(&glb(&document, &buffer), Path::new("")).unwrap();
+        assert_eq!(skipped, SkippedPrimitives::default());
+        assert_eq!(scene.objects.len(), 2);
+        // The second node doubles the triangle and moves it along with its parent.
+        let hit = scene.objects[1]
+            .intersection(&Vec3::new(3.5, 1.5, 0f32), &Vec3::NEG_Z)
+            .unwrap();
+        assert!(hit.point.abs_diff_eq(Vec3::new(3.5, 1.5, -5f32), 1e-5));
+        assert!(
+            scene.objects[0]
+                .intersection(&Vec3::new(0.8, 0.8, 0f32), &Vec3::NEG_Z)
+                .is_none()
+        );
+
+        assert_eq!(scene.camera.position, Vec3::new(0f32, 0f32, 1f32));
+        assert_eq!(scene.camera.target, Vec3::ZERO);
+        assert!((scene.camera.fov - 1f32.to_degrees()).abs() < 1e-4);
+        assert_eq!(scene.camera.aspect, Some(1.5));
+        assert_eq!(scene.lights.len(), 1);
+        assert!(matches!(
+            scene.lights[0].kind,
+            LightKind::Point { position } if position == Vec3::new(0f32, 0f32, 1f32)
+        ));
+        assert!(matches!(
+            scene.lights[0].attenuation,
+            Attenuation::Smooth { range } if range == 10f32
+        ));
+
+        // Rays cannot be moved into the space of a node that flattens its mesh.
+        let mut flattened = document.clone();
+        flattened["nodes"][2]["scale"] = serde_json::json!([2, 0, 2]);
+        assert!(parse_gltf(&glb(&flattened, &buffer), Path::new("")).is_err());
+    }
+
+    #[test]
+    fn points_and_line_segments_are_counted_and_left_out() {
+        let buffer: Vec<u8> = [0f32, 0f32, 0f32, 1f32, 0f32, 0f32, 0f32, 1f32, 0f32]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let primitives: Vec<serde_json::Value> = (0..5)
+            .map(|mode| serde_json::json!({ "attributes": { "POSITION": 0 }, "mode": mode }))
+            .collect();
+        let document = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": primitives }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }],
+            "bufferViews": [{ "buffer": 0, "byteLength": buffer.len() }],
+            "buffers": [{ "byteLength": buffer.len() }]
+        });
+
+        let (scene, skipped) = parse_gltf(&glb(&document, &buffer), Path::new("")).unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        // Three vertices make one line, a loop of three segments and a strip of two.
+        assert_eq!(
+            skipped,
+            SkippedPrimitives {
+                points: 3,
+                lines: 1 + 3 + 2
+            }
+        );
+    }
+
+    #[test]
+    fn accessors_outside_of_their_buffer_are_rejected() {
+        let buffer: Vec<u8> = [0f32; 9]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let document = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }],
+            "bufferViews": [{ "buffer": 0, "byteLength": buffer.len() }],
+            "buffers": [{ "byteLength": buffer.len() }]
+        });
+        assert!(parse_gltf(&glb(&document, &buffer), Path::new("")).is_ok());
+
+        let broken = [
+            // Would reserve far more memory than the buffer could ever hold.
+            ("/accessors/0", "count", serde_json::json!(1u64 << 60)),
+            ("/accessors/0", "byteOffset", serde_json::json!(4)),
+            (
+                "/bufferViews/0",
+                "byteOffset",
+                serde_json::json!(usize::MAX),
+            ),
+            ("/bufferViews/0", "byteStride", serde_json::json!(16)),
+        ];
+        for (pointer, key, value) in broken {
+            let mut document = document.clone();
+            document.pointer_mut(pointer).unwrap()[key] = value;
+            let error = parse_gltf(&glb(&document, &buffer), Path::new(""))
+                .err()
+                .unwrap_or_else(|| panic!("{pointer}/{key} should be rejected"));
+            assert!(error.contains("extends past the end"), "{key}: {error}");
+        }
+
+        // Without a buffer view, nothing but the count says how much memory to reserve.
+        for count in [1u64 << 30, 1u64 << 60] {
+            let mut bufferless = document.clone();
+            let accessor = bufferless.pointer_mut("/accessors/0").unwrap();
+            accessor.as_object_mut().unwrap().remove("bufferView");
+            accessor["count"] = serde_json::json!(count);
+            let error = parse_gltf(&glb(&bufferless, &buffer), Path::new(""))
+                .err()
+                .unwrap_or_else(|| panic!("a count of {count} should be rejected"));
+            assert!(error.contains("too many elements"), "{error}");
+        }
+    }
+
+    #[test]
+    fn light_ranges_only_fade_lights_out_toward_the_end() {
+        let light = |range: serde_json::Value| {
+            let light: PunctualLight = serde_json::from_value(serde_json::json!({
+                "type": "point",
+                "intensity": 10,
+                "range": range
+            }))
+            .unwrap();
+            light.to_light(&Mat4::IDENTITY)
+        };
+        let unlimited = light(serde_json::Value::Null).unwrap();
+        let limited = light(serde_json::json!(100)).unwrap();
+        for distance in [0.5, 1f32, 5f32] {
+            let point = Vec3::new(0f32, distance, 0f32);
+            let arriving = |light: &Light| {
+                let sample = light.sample(&point, Vec2::splat(0.5)).unwrap();
+                sample.radiance * sample.attenuation
+            };
+            let ratio = arriving(&limited) / arriving(&unlimited);
+            assert!(ratio.cmpgt(Vec3::splat(0.99)).all(), "{distance}: {ratio}");
+        }
+        assert!(light(serde_json::json!(0)).is_err());
+        assert!(light(serde_json::json!(-5)).is_err());
+    }
+
+    #[test]
+    fn samplers_wrap_each_axis_on_its_own() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(1, 1)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+        let document: Document = serde_json::from_value(serde_json::json!({
+            "textures": [{ "source": 0, "sampler": 0 }, { "source": 0, "sampler": 1 }],
+            "images": [{ "bufferView": 0 }],
+            "samplers": [{ "wrapS": 33071, "wrapT": 33648 }, { "wrapS": 33071 }],
+            "bufferViews": [{ "buffer": 0, "byteLength": png.len() }],
+            "buffers": [{ "byteLength": png.len() }]
+        }))
+        .unwrap();
+        let mut loader = Loader {
+            document: &document,
+            buffers: vec![png],
+            directory: Path::new(""),
+            images: HashMap::new(),
+            skipped: SkippedPrimitives::default(),
+        };
+        let mut wrap =
+            |index| match loader.texture(&TextureInfo { index }, ImageChannels::Color, Vec3::ONE) {
+                Ok(Texture::Image(texture)) => texture.wrap,
+                _ => panic!("textures[{index}] should load as an image"),
+            };
+
+        assert_eq!(wrap(0), [WrapMode::Clamp, WrapMode::Mirror]);
+        // Either axis that is left out repeats.
+        assert_eq!(wrap(1), [WrapMode::Clamp, WrapMode::Repeat]);
+    }
+
+    #[test]
+    fn base64_decodes_with_and_without_padding() {
+        assert_eq!(decode_base64("Z2xURg==").unwrap(), b"glTF");
+        assert_eq!(decode_base64("Z2xURjI").unwrap(), b"glTF2");
+        assert!(decode_base64("Z2x*").is_err());
+    }
+}
@@ -1,16 +1,63 @@
+#![allow(clippy::needless_return)]
+
 use std::{
+    collections::HashMap,
     fs::{self, File},
+    io::{BufReader, ErrorKind},
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use clap::Parser;
-use glam::Vec3;
-use image::{DynamicImage, ImageReader};
-use serde_json::Value;
+use glam::{Vec2, Vec3};
+use image::{DynamicImage, ImageReader, Rgb32FImage};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
 use wavefront_obj::obj::{self, ObjSet, Primitive};
 
-use crate::structures::{AABB, Light, Material, Scene, Sphere, Traceable, Triangle};
+use crate::error::SceneError;
+use crate::gltf;
+use crate::mtl::{self, MtlMaterial};
+use crate::output::{ToneMapOperator, srgb_to_linear};
+use crate::polygon;
+use crate::raytracer::Integrator;
+use crate::sampling::{PixelFilter, SamplePattern, SamplingSettings};
+use crate::structures::{
+    AABB, Camera, Emitters, Instance, Light, Material, Mesh, RenderSettings, Scene, Sphere,
+    Traceable, Transform, Triangle, validate_matrix,
+};
+use crate::texture::{Texture, TextureUsage};
+
+/// How shading normals are chosen for an imported mesh.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalSource {
+    /// Use the normals from the file, generating smooth normals for faces without any.
+    #[default]
+    Auto,
+    /// Always generate smooth normals, ignoring any in the file.
+    Generated,
+    /// Use the flat face normal of every triangle.
+    Flat,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct MeshShading {
+    pub normals: NormalSource,
+    /// Angle in degrees above which generated normals keep an edge sharp.
+    pub crease_angle: f32,
+}
+
+impl Default for MeshShading {
+    fn default() -> Self {
+        MeshShading {
+            normals: NormalSource::Auto,
+            crease_angle: 60f32,
+        }
+    }
+}
 
 #[derive(Parser)]
 struct Arguments {
@@ -22,28 +69,103 @@ struct Arguments {
 
     #[arg(short, long)]
     background: Option<String>,
+
+    /// Camera position as x,y,z
+    #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+    camera_position: Option<Vec3>,
+
+    /// Point the camera looks at as x,y,z
+    #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+    camera_target: Option<Vec3>,
+
+    /// Camera up vector as x,y,z
+    #[arg(long, value_parser = parse_vector, allow_hyphen_values = true)]
+    camera_up: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[arg(long)]
+    fov: Option<f32>,
+
+    /// Width divided by height of the image plane, instead of following the output image
+    #[arg(long)]
+    aspect: Option<f32>,
+
+    /// Output image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Output image height in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+
+    /// Number of anti-aliasing samples per pixel
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+
+    /// Placement of the anti-aliasing samples within a pixel
+    #[arg(long)]
+    sample_pattern: Option<SamplePattern>,
+
+    /// Reconstruction filter used to combine samples into pixels
+    #[arg(long)]
+    filter: Option<PixelFilter>,
+
+    /// Half-width of the reconstruction filter in pixels
+    #[arg(long, value_parser = parse_filter_radius)]
+    filter_radius: Option<f32>,
+
+    /// Seed for randomized sample patterns
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Operator used to map high dynamic range radiance to displayable colors
+    #[arg(long)]
+    tone_map: Option<ToneMapOperator>,
+
+    /// Exposure adjustment in stops, applied before tone mapping
+    #[arg(long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+
+    /// Light transport algorithm used to render the scene
+    #[arg(long)]
+    integrator: Option<Integrator>,
 }
 
 pub struct ExecutionContext {
     pub scene: Scene,
+    pub settings: RenderSettings,
     pub output: String,
 }
 
-pub fn initialize() -> ExecutionContext {
+#[allow(clippy::needless_late_init)]
+pub fn initialize() -> Result<ExecutionContext, Vec<SceneError>> {
     let arguments = Arguments::parse();
 
-    let mut scene: Scene;
-    match arguments.scene.as_deref() {
-        Some(scene_file_path) => scene = parse_scene_json(scene_file_path),
-        None => scene = default_scene(),
-    }
+    let (mut scene, mut settings) = match arguments.scene.as_deref() {
+        Some(scene_file_path) if gltf::is_gltf(scene_file_path) => {
+            (gltf::load_gltf(scene_file_path)?, RenderSettings::default())
+        }
+        Some(scene_file_path) => parse_scene_json(scene_file_path)?,
+        None => (default_scene()?, RenderSettings::default()),
+    };
+    apply_settings_arguments(&mut settings, &arguments);
 
-    let background: DynamicImage;
+    let background: Option<Rgb32FImage>;
     match arguments.background.as_deref() {
         Some(background_file_path) => background = load_background(background_file_path),
         None => background = load_background("envmap.jpg"),
     }
-    scene.background = Some(background);
+    scene.background = background;
+    apply_camera_arguments(&mut scene.camera, &arguments);
+    // Checked after the arguments are applied, as they may repair or break the camera.
+    if let Err(message) = scene.camera.validate() {
+        return Err(vec![SceneError::InvalidValue {
+            field: String::from("camera"),
+            message,
+        }]);
+    }
+    scene.build_bvh();
+    scene.collect_emitters();
 
     let output: String;
     match arguments.output.as_deref() {
@@ -51,65 +173,731 @@ pub fn initialize() -> ExecutionContext {
         None => output = String::from("out.png"),
     }
 
-    ExecutionContext { scene, output }
+    Ok(ExecutionContext {
+        scene,
+        settings,
+        output,
+    })
+}
+
+fn parse_vector(argument: &str) -> Result<Vec3, String> {
+    let components = argument
+        .split(',')
+        .map(|component| component.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|error| error.to_string())?;
+    if components.len() != 3 {
+        return Err(String::from(
+            "expected three comma-separated numbers (x,y,z)",
+        ));
+    }
+    return Ok(Vec3::from_slice(&components));
 }
 
-fn parse_scene_json(scene_file_path: &std::path::Path) -> Scene {
-    // TODO: If any errors while trying to read the scene JSON, return the default scene.
-    // replace expects with unwrap_or and handle more gracefully
-    let scene_json = File::open(scene_file_path).expect("Scene file not found!");
+fn parse_filter_radius(argument: &str) -> Result<f32, String> {
+    let radius = argument
+        .trim()
+        .parse::<f32>()
+        .map_err(|error| error.to_string())?;
+    if !(radius > 0f32 && radius.is_finite()) {
+        return Err(String::from("must be a positive number of pixels"));
+    }
+    return Ok(radius);
+}
+
+fn apply_camera_arguments(camera: &mut Camera, arguments: &Arguments) {
+    if let Some(position) = arguments.camera_position {
+        camera.position = position;
+    }
+    if let Some(target) = arguments.camera_target {
+        camera.target = target;
+    }
+    if let Some(up) = arguments.camera_up {
+        camera.up = up;
+    }
+    if let Some(fov) = arguments.fov {
+        camera.fov = fov;
+    }
+    if let Some(aspect) = arguments.aspect {
+        camera.aspect = Some(aspect);
+    }
+}
+
+fn apply_settings_arguments(settings: &mut RenderSettings, arguments: &Arguments) {
+    if let Some(width) = arguments.width {
+        settings.resolution.width = width;
+    }
+    if let Some(height) = arguments.height {
+        settings.resolution.height = height;
+    }
+    if let Some(samples) = arguments.samples {
+        settings.sampling.samples = samples;
+    }
+    if let Some(pattern) = arguments.sample_pattern {
+        settings.sampling.pattern = pattern;
+    }
+    if let Some(filter) = arguments.filter {
+        settings.sampling.filter = filter;
+    }
+    if let Some(filter_radius) = arguments.filter_radius {
+        settings.sampling.filter_radius = Some(filter_radius);
+    }
+    if let Some(seed) = arguments.seed {
+        settings.sampling.seed = seed;
+    }
+    if let Some(operator) = arguments.tone_map {
+        settings.tone_mapping.operator = operator;
+    }
+    if let Some(exposure) = arguments.exposure {
+        settings.tone_mapping.exposure = exposure;
+    }
+    if let Some(integrator) = arguments.integrator {
+        settings.integrator = integrator;
+    }
+}
+
+fn parse_scene_json(scene_file_path: &Path) -> Result<(Scene, RenderSettings), Vec<SceneError>> {
+    let scene_json = File::open(scene_file_path).map_err(|source| {
+        vec![SceneError::MissingFile {
+            path: scene_file_path.to_path_buf(),
+            source,
+        }]
+    })?;
     let raw_data: Value =
-        serde_json::from_reader(scene_json).expect("Scene file is not valid JSON.");
-    let lights: Vec<Light> = serde_json::from_value(raw_data["lights"].clone())
-        .expect("Scene file does not contain any lights.");
-    let objects_json: Vec<Value> = serde_json::from_value(raw_data["objects"].clone())
-        .expect("Scene file does not contain any objects.");
+        serde_json::from_reader(BufReader::new(scene_json)).map_err(|source| {
+            vec![SceneError::InvalidJson {
+                path: scene_file_path.to_path_buf(),
+                source,
+            }]
+        })?;
+
+    let mut errors: Vec<SceneError> = Vec::new();
+
+    let lights_json: Vec<Value> =
+        required_field(&raw_data, "", "lights", &mut errors).unwrap_or_default();
+    let lights: Vec<Light> = lights_json
+        .iter()
+        .enumerate()
+        .filter_map(|(index, light)| {
+            // Scenes written before other light types existed only contain point lights.
+            let mut light = light.clone();
+            if let Some(light) = light.as_object_mut() {
+                light.entry("type").or_insert_with(|| Value::from("point"));
+            }
+            let field = format!("lights[{index}]");
+            let light: Light = deserialize_field(&light, &field, &mut errors)?;
+            if let Err(message) = light.attenuation.validate() {
+                errors.push(SceneError::InvalidValue {
+                    field: field_path(&field, "attenuation"),
+                    message,
+                });
+            }
+            Some(light)
+        })
+        .collect();
+    let objects_json: Vec<Value> =
+        required_field(&raw_data, "", "objects", &mut errors).unwrap_or_default();
+    let camera: Camera = optional_field(&raw_data, "", "camera", &mut errors).unwrap_or_default();
+
+    let mut settings = RenderSettings::default();
+    if let Some(resolution) = optional_field(&raw_data, "", "resolution", &mut errors) {
+        settings.resolution = resolution;
+        if settings.resolution.width == 0 || settings.resolution.height == 0 {
+            errors.push(SceneError::InvalidValue {
+                field: String::from("resolution"),
+                message: String::from("must be at least one pixel wide and tall"),
+            });
+        }
+    }
+    if let Some(sampling) =
+        optional_field::<SamplingSettings>(&raw_data, "", "sampling", &mut errors)
+    {
+        settings.sampling = sampling;
+        if let Some(radius) = sampling.filter_radius
+            && !(radius > 0f32 && radius.is_finite())
+        {
+            errors.push(SceneError::InvalidValue {
+                field: String::from("sampling.filter_radius"),
+                message: String::from("must be a positive number of pixels"),
+            });
+        }
+    }
+    if let Some(tone_mapping) = optional_field(&raw_data, "", "tone_mapping", &mut errors) {
+        settings.tone_mapping = tone_mapping;
+    }
+    if let Some(integrator) = optional_field(&raw_data, "", "integrator", &mut errors) {
+        settings.integrator = integrator;
+    }
+
+    // Meshes and textures are referenced relative to the scene file.
+    let scene_directory = scene_file_path.parent().unwrap_or(Path::new(""));
+    // Named materials can be used by meshes as well, so they are read first.
+    let materials_json: Map<String, Value> =
+        optional_field(&raw_data, "", "materials", &mut errors).unwrap_or_default();
+    let materials = material_library(&materials_json, scene_directory, &mut errors);
+
+    let meshes_json: Map<String, Value> =
+        optional_field(&raw_data, "", "meshes", &mut errors).unwrap_or_default();
+    let mut meshes: HashMap<String, Arc<Mesh>> = HashMap::new();
+    for (name, mesh) in meshes_json.iter() {
+        let path = field_path("meshes", name);
+        let triangles = mesh_field(mesh, &path, scene_directory, &materials, &mut errors);
+        if let Some(mesh) = triangles.and_then(Mesh::new) {
+            meshes.insert(name.clone(), Arc::new(mesh));
+        }
+    }
 
     let mut objects: Vec<Box<dyn Traceable>> = Vec::new();
-    for object in objects_json {
-        let object_name: String = serde_json::from_value(object["name"].clone()).unwrap();
+    for (index, object) in objects_json.iter().enumerate() {
+        let path = format!("objects[{index}]");
+        let Some(object_name) = required_field::<String>(object, &path, "name", &mut errors) else {
+            continue;
+        };
+        let transform: Option<Transform> = optional_field(object, &path, "transform", &mut errors);
+        let mut parts: Vec<Box<dyn Traceable>> = Vec::new();
         match object_name.deref() {
             "sphere" => {
-                let radius: f32 = serde_json::from_value(object["radius"].clone()).unwrap();
-                let center: Vec3 = serde_json::from_value(object["center"].clone()).unwrap();
-                let material: Material =
-                    serde_json::from_value(object["material"].clone()).unwrap();
-                objects.push(Box::new(Sphere {
-                    center,
-                    radius,
-                    material,
-                }));
+                let radius = required_field(object, &path, "radius", &mut errors);
+                let center = required_field(object, &path, "center", &mut errors);
+                let material =
+                    material_field(object, &path, scene_directory, &materials, &mut errors);
+                if let (Some(radius), Some(center), Some(material)) = (radius, center, material) {
+                    parts.push(Box::new(Sphere {
+                        center,
+                        radius,
+                        material,
+                    }));
+                }
             }
             "box" => {
-                let min: Vec3 = serde_json::from_value(object["min"].clone()).unwrap();
-                let max: Vec3 = serde_json::from_value(object["max"].clone()).unwrap();
-                let material: Material =
-                    serde_json::from_value(object["material"].clone()).unwrap();
-                objects.push(Box::new(AABB { min, max, material }));
+                let min = required_field(object, &path, "min", &mut errors);
+                let max = required_field(object, &path, "max", &mut errors);
+                let material =
+                    material_field(object, &path, scene_directory, &materials, &mut errors);
+                if let (Some(min), Some(max), Some(material)) = (min, max, material) {
+                    parts.push(Box::new(AABB { min, max, material }));
+                }
             }
-            _ => {
-                println!("Unknown object: {}", object_name)
+            "mesh" => {
+                if let Some(triangles) =
+                    mesh_field(object, &path, scene_directory, &materials, &mut errors)
+                {
+                    parts = triangles;
+                }
+            }
+            "instance" => {
+                let Some(mesh_name) = required_field::<String>(object, &path, "mesh", &mut errors)
+                else {
+                    continue;
+                };
+                match meshes.get(&mesh_name) {
+                    Some(mesh) => match Instance::new(mesh.clone(), &transform.unwrap_or_default())
+                    {
+                        Ok(instance) => objects.push(Box::new(instance)),
+                        Err(message) => errors.push(SceneError::InvalidValue {
+                            field: field_path(&path, "transform"),
+                            message,
+                        }),
+                    },
+                    // Meshes that failed to load have already been reported.
+                    None if meshes_json.contains_key(&mesh_name) => {}
+                    None => errors.push(SceneError::UndefinedName {
+                        field: field_path(&path, "mesh"),
+                        kind: "mesh",
+                        name: mesh_name,
+                    }),
+                }
+                continue;
             }
+            _ => errors.push(SceneError::UnknownObject {
+                field: field_path(&path, "name"),
+                name: object_name,
+            }),
         }
+        match transform {
+            Some(transform) => {
+                if let Some(mesh) = Mesh::new(parts) {
+                    match Instance::new(Arc::new(mesh), &transform) {
+                        Ok(instance) => objects.push(Box::new(instance)),
+                        Err(message) => errors.push(SceneError::InvalidValue {
+                            field: field_path(&path, "transform"),
+                            message,
+                        }),
+                    }
+                }
+            }
+            None => objects.extend(parts),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
     }
 
-    return Scene {
+    let scene = Scene {
         lights,
         objects,
         background: None,
+        camera,
+        bvh: None,
+        emitters: Emitters::default(),
+    };
+    return Ok((scene, settings));
+}
+
+/// Joins a JSON path and a key, e.g. `objects[3]` and `material` into `objects[3].material`.
+fn field_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() || key.starts_with('[') {
+        return format!("{parent}{key}");
+    }
+    return format!("{parent}.{key}");
+}
+
+fn deserialize_field<T: DeserializeOwned>(
+    value: &Value,
+    field: &str,
+    errors: &mut Vec<SceneError>,
+) -> Option<T> {
+    match serde_path_to_error::deserialize(value) {
+        Ok(parsed) => return Some(parsed),
+        Err(error) => {
+            let nested_path = error.path().to_string();
+            let field = match nested_path.as_str() {
+                "." => String::from(field),
+                nested_path => field_path(field, nested_path),
+            };
+            let message = error.into_inner().to_string();
+            // serde reports a missing struct member against the struct that should contain it,
+            // with a fixed wording that `nested_errors_name_the_full_path` relies on.
+            let missing_member = message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.strip_suffix('`'));
+            match missing_member {
+                Some(member) => errors.push(SceneError::MissingField {
+                    field: field_path(&field, member),
+                }),
+                None => errors.push(SceneError::WrongType { field, message }),
+            }
+            return None;
+        }
+    }
+}
+
+fn required_field<T: DeserializeOwned>(
+    parent: &Value,
+    parent_path: &str,
+    key: &str,
+    errors: &mut Vec<SceneError>,
+) -> Option<T> {
+    let field = field_path(parent_path, key);
+    match parent.get(key) {
+        Some(value) => return deserialize_field(value, &field, errors),
+        None => {
+            errors.push(SceneError::MissingField { field });
+            return None;
+        }
+    }
+}
+
+/// Loads the OBJ file at the `path` of a `"mesh"` object or entry of `"meshes"`, placed by the
+/// transform fields next to it. Without a `material`, the groups of the mesh use the materials
+/// of the file's own `mtllib`.
+fn mesh_field(
+    object: &Value,
+    path: &str,
+    directory: &Path,
+    materials: &MaterialLibrary,
+    errors: &mut Vec<SceneError>,
+) -> Option<Vec<Box<dyn Traceable>>> {
+    let mesh_path: Option<PathBuf> = required_field(object, path, "path", errors);
+    let material = object
+        .get("material")
+        .map(|_| material_field(object, path, directory, materials, errors));
+    // The transform fields sit directly on the object, next to `path` and `material`.
+    let transform: Option<Transform> = deserialize_field(object, path, errors);
+    let shading: Option<MeshShading> = deserialize_field(object, path, errors);
+    let mesh_path = directory.join(mesh_path?);
+    let field = field_path(path, "path");
+    let model = load_obj(&mesh_path, Some(field.clone()))
+        .map_err(|error| errors.push(error))
+        .ok()?;
+    let mesh_materials = match (material, &model.material_library) {
+        (Some(material), _) => MeshMaterials::Uniform(material?),
+        (None, Some(library)) => {
+            let library = mesh_path.parent().unwrap_or(Path::new("")).join(library);
+            load_material_library(&library, &field, errors)?
+        }
+        (None, None) => {
+            errors.push(SceneError::MissingField {
+                field: field_path(path, "material"),
+            });
+            return None;
+        }
     };
+    let (transform, shading) = (transform?, shading?);
+    if let Err(message) = validate_matrix(&transform.matrix()) {
+        errors.push(SceneError::InvalidValue {
+            field: String::from(path),
+            message,
+        });
+        return None;
+    }
+    let mut triangles: Vec<Box<dyn Traceable>> = Vec::new();
+    add_triangulated_mesh(
+        &model,
+        &mut triangles,
+        &mesh_materials,
+        &transform,
+        &shading,
+    );
+    return Some(triangles);
 }
 
-fn load_background(background: &str) -> DynamicImage {
-    // TODO: Fallback to using background color and provide a warning instead of panicking if image is not found.
-    // Should be simple. Just handle errors and return None.
-    return ImageReader::open(background)
-        .expect("Environment map not found!")
-        .decode()
-        .expect("Unable to decode environment map. Is the file intact?");
+/// Reads the materials of an OBJ file's `mtllib`, along with their textures, which are
+/// referenced relative to the library. A library that does not exist leaves every group gray.
+fn load_material_library(
+    path: &Path,
+    field: &str,
+    errors: &mut Vec<SceneError>,
+) -> Option<MeshMaterials> {
+    let fallback = MtlMaterial::new("").to_material();
+    let library = match fs::read_to_string(path) {
+        // Groups without a known material are gray anyway, so a missing library only costs the
+        // mesh its colors.
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            eprintln!(
+                "Warning: material library {} does not exist, so {field} is rendered in gray.",
+                path.display()
+            );
+            return Some(MeshMaterials::Library {
+                materials: HashMap::new(),
+                fallback,
+            });
+        }
+        library => library
+            .map_err(|error| error.to_string())
+            .and_then(|contents| mtl::parse(&contents)),
+    };
+    let library = match library {
+        Ok(library) => library,
+        Err(message) => {
+            errors.push(SceneError::BadMaterialLibrary {
+                field: String::from(field),
+                path: path.to_path_buf(),
+                message,
+            });
+            return None;
+        }
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let materials = library
+        .iter()
+        .map(|material| {
+            let mut converted = material.to_material();
+            load_textures(&mut converted, directory, field, errors);
+            material.tint_diffuse_map(&mut converted);
+            (material.name.clone(), converted)
+        })
+        .collect();
+    return Some(MeshMaterials::Library {
+        materials,
+        fallback,
+    });
+}
+
+/// Materials that objects can refer to by name. Definitions that failed to parse are kept as
+/// `None`, so that their errors are not repeated for every object using them.
+type MaterialLibrary = HashMap<String, Option<Material>>;
+
+/// The built-in materials along with the scene's `"materials"`, which replace built-in ones of
+/// the same name.
+fn material_library(
+    materials_json: &Map<String, Value>,
+    directory: &Path,
+    errors: &mut Vec<SceneError>,
+) -> MaterialLibrary {
+    let mut materials: MaterialLibrary = Material::PREDEFINED
+        .into_iter()
+        .map(|(name, material)| (String::from(name), Some(material)))
+        .collect();
+    for (name, material) in materials_json.iter() {
+        let field = field_path("materials", name);
+        let material = parse_material(material, &field, directory, errors);
+        materials.insert(name.clone(), material);
+    }
+    return materials;
 }
 
-fn default_scene() -> Scene {
+/// Reads the `material` of an object, which is either defined inline or the name of one of
+/// `materials`.
+fn material_field(
+    parent: &Value,
+    parent_path: &str,
+    directory: &Path,
+    materials: &MaterialLibrary,
+    errors: &mut Vec<SceneError>,
+) -> Option<Material> {
+    let field = field_path(parent_path, "material");
+    let Some(value) = parent.get("material") else {
+        errors.push(SceneError::MissingField { field });
+        return None;
+    };
+    let Some(name) = value.as_str() else {
+        return parse_material(value, &field, directory, errors);
+    };
+    match materials.get(name) {
+        Some(material) => return material.clone(),
+        None => {
+            errors.push(SceneError::UndefinedName {
+                field,
+                kind: "material",
+                name: String::from(name),
+            });
+            return None;
+        }
+    }
+}
+
+/// Reads a material definition. The variant is deserialized directly rather than through the
+/// tagged `Material` enum so that errors keep their full path, e.g. `material.albedo[1]`.
+fn parse_material(
+    value: &Value,
+    field: &str,
+    directory: &Path,
+    errors: &mut Vec<SceneError>,
+) -> Option<Material> {
+    // Scenes written before the PBR model existed have no `model` and are all Phong.
+    let model: String = match value.get("model") {
+        Some(_) => required_field(value, field, "model", errors)?,
+        None => String::from("phong"),
+    };
+    let mut material = match model.as_str() {
+        "phong" => deserialize_field(value, field, errors).map(Material::Phong),
+        "pbr" => deserialize_field(value, field, errors).map(Material::Pbr),
+        _ => {
+            errors.push(SceneError::InvalidValue {
+                field: field_path(field, "model"),
+                message: format!("unknown material model \"{model}\", expected phong or pbr"),
+            });
+            None
+        }
+    }?;
+    load_textures(&mut material, directory, field, errors);
+    return Some(material);
+}
+
+/// Reads the images of the image textures in `material` from disk. Only color slots decode them
+/// from sRGB.
+fn load_textures(
+    material: &mut Material,
+    directory: &Path,
+    field: &str,
+    errors: &mut Vec<SceneError>,
+) {
+    for (slot, usage, texture) in material.textures_mut() {
+        let Texture::Image(texture) = texture else {
+            continue;
+        };
+        let path = directory.join(&texture.path);
+        let image = ImageReader::open(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|reader| reader.decode().map_err(|error| error.to_string()));
+        match image {
+            Ok(image) => {
+                let image = match usage {
+                    TextureUsage::Color => linear_image(image),
+                    TextureUsage::Data => image.into_rgb32f(),
+                };
+                texture.image = Some(Arc::new(image));
+            }
+            Err(message) => errors.push(SceneError::BadTexture {
+                field: field_path(&field_path(field, slot), "path"),
+                path,
+                message,
+            }),
+        }
+    }
+}
+
+fn optional_field<T: DeserializeOwned>(
+    parent: &Value,
+    parent_path: &str,
+    key: &str,
+    errors: &mut Vec<SceneError>,
+) -> Option<T> {
+    return parent
+        .get(key)
+        .and_then(|value| deserialize_field(value, &field_path(parent_path, key), errors));
+}
+
+fn load_background(background: &str) -> Option<Rgb32FImage> {
+    let image = ImageReader::open(background)
+        .map_err(|error| error.to_string())
+        .and_then(|reader| reader.decode().map_err(|error| error.to_string()));
+    match image {
+        Ok(image) => return Some(linear_image(image)),
+        Err(error) => {
+            eprintln!(
+                "Warning: unable to load environment map {background} ({error}). Falling back to the background color."
+            );
+            return None;
+        }
+    }
+}
+
+/// Converts an image to linear floating point values. Floating point formats such as `.hdr` are
+/// already linear, while 8 and 16 bit images are assumed to be sRGB encoded.
+pub fn linear_image(image: DynamicImage) -> Rgb32FImage {
+    let is_linear = matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+    let mut linear = image.into_rgb32f();
+    if !is_linear {
+        linear
+            .pixels_mut()
+            .for_each(|pixel| pixel.0 = pixel.0.map(srgb_to_linear));
+    }
+    return linear;
+}
+
+fn load_obj(path: &Path, field: Option<String>) -> Result<ObjSet, SceneError> {
+    let bad_mesh_path = |message: String| SceneError::BadMeshPath {
+        field: field.clone(),
+        path: path.to_path_buf(),
+        message,
+    };
+    let contents = fs::read_to_string(path).map_err(|error| bad_mesh_path(error.to_string()))?;
+    let (contents, skipped) = triangulate_faces(&contents);
+    skipped.report(path);
+    return obj::parse(contents).map_err(|error| bad_mesh_path(error.to_string()));
+}
+
+/// Primitives of a mesh file that are left out, as they have no surface to render. Lines are
+/// counted by segment, so a polyline through n vertices adds n - 1.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SkippedPrimitives {
+    pub points: usize,
+    pub lines: usize,
+}
+
+impl SkippedPrimitives {
+    /// Warns about the skipped primitives of the file at `path`, if there are any.
+    pub fn report(&self, path: &Path) {
+        if self.points > 0 || self.lines > 0 {
+            eprintln!(
+                "Warning: skipped {} points and {} line segments of {}, which have no surface to render.",
+                self.points,
+                self.lines,
+                path.display()
+            );
+        }
+    }
+}
+
+/// Rewrites the faces of an OBJ file into triangles before it is parsed. The parser would fan
+/// polygons out from their last corner, which breaks concave ones, so they are ear clipped
+/// instead. It also rejects relative indices, so those are made absolute. Points and lines are
+/// removed, as the parser either rejects them or turns polylines into triangles. Faces with
+/// indices that cannot be resolved are left for the parser to report.
+fn triangulate_faces(contents: &str) -> (String, SkippedPrimitives) {
+    let mut skipped = SkippedPrimitives::default();
+    let mut positions: Vec<Vec3> = Vec::new();
+    let (mut texture_count, mut normal_count) = (0, 0);
+    let mut output = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next();
+        let arguments: Vec<&str> = tokens.collect();
+        match (keyword, arguments.len()) {
+            (Some("v"), _) => {
+                let coordinate = |axis: usize| {
+                    arguments
+                        .get(axis)
+                        .and_then(|argument| argument.parse().ok())
+                        .unwrap_or(0f32)
+                };
+                positions.push(Vec3::new(coordinate(0), coordinate(1), coordinate(2)));
+            }
+            (Some("vt"), _) => texture_count += 1,
+            (Some("vn"), _) => normal_count += 1,
+            (Some("p"), count) => {
+                skipped.points += count;
+                continue;
+            }
+            (Some("l"), count) => {
+                skipped.lines += count.saturating_sub(1);
+                continue;
+            }
+            (Some("f"), 2) => {
+                skipped.lines += 1;
+                continue;
+            }
+            (Some("f"), 1) => {
+                skipped.points += 1;
+                continue;
+            }
+            (Some("f"), count) if count >= 3 => {
+                let counts = [positions.len(), texture_count, normal_count];
+                let corners: Option<Vec<(usize, String)>> = arguments
+                    .iter()
+                    .map(|corner| absolute_corner(corner, counts))
+                    .collect();
+                if let Some(corners) = corners {
+                    let triangles = match count {
+                        3 => vec![[0, 1, 2]],
+                        _ => {
+                            let points: Vec<Vec3> =
+                                corners.iter().map(|(index, _)| positions[*index]).collect();
+                            polygon::triangulate(&points)
+                        }
+                    };
+                    for triangle in triangles {
+                        let [a, b, c] = triangle.map(|corner| &corners[corner].1);
+                        output.push_str(&format!("f {a} {b} {c}\n"));
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    return (output, skipped);
+}
+
+/// Resolves a face corner such as `-1/-1/-1` against the `counts` of positions, texture
+/// coordinates and normals read so far. Indices are 1-based, or relative to the end of the
+/// elements read so far. Returns the 0-based position and the corner with absolute indices.
+fn absolute_corner(corner: &str, counts: [usize; 3]) -> Option<(usize, String)> {
+    let parts: Vec<&str> = corner.split('/').collect();
+    if parts.len() > 3 || parts[0].is_empty() {
+        return None;
+    }
+    let indices: Vec<Option<usize>> = parts
+        .iter()
+        .zip(counts)
+        .map(|(part, count)| {
+            if part.is_empty() {
+                return Some(None);
+            }
+            let index: isize = part.parse().ok()?;
+            let index = match index < 0 {
+                true => count.checked_add_signed(index)?,
+                false => index.checked_sub(1)? as usize,
+            };
+            return (index < count).then_some(Some(index));
+        })
+        .collect::<Option<_>>()?;
+    let rewritten: Vec<String> = indices
+        .iter()
+        .map(|index| index.map_or(String::new(), |index| (index + 1).to_string()))
+        .collect();
+    return Some((indices[0]?, rewritten.join("/")));
+}
+
+fn default_scene() -> Result<Scene, Vec<SceneError>> {
     let mut objects: Vec<Box<dyn Traceable>> = Vec::new();
     let mut lights = Vec::new();
 
@@ -130,80 +918,592 @@ fn default_scene() -> Scene {
         material: Material::GREEN,
     }));
 
-    let goblet_obj = fs::read_to_string("goblet.obj").unwrap();
-    let goblet = obj::parse(goblet_obj).unwrap();
-    let seashell_obj = fs::read_to_string("seashell.obj").unwrap();
-    let seashell = obj::parse(seashell_obj).unwrap();
-    let boat_obj = fs::read_to_string("boat.obj").unwrap();
-    let boat = obj::parse(boat_obj).unwrap();
+    let goblet = load_obj(Path::new("goblet.obj"), None);
+    let seashell = load_obj(Path::new("seashell.obj"), None);
+    let boat = load_obj(Path::new("boat.obj"), None);
+    let (goblet, seashell, boat) = match (goblet, seashell, boat) {
+        (Ok(goblet), Ok(seashell), Ok(boat)) => (goblet, seashell, boat),
+        (goblet, seashell, boat) => {
+            return Err([goblet.err(), seashell.err(), boat.err()]
+                .into_iter()
+                .flatten()
+                .collect());
+        }
+    };
 
     add_triangulated_mesh(
         &goblet,
         &mut objects,
-        Material::PURPLE,
-        Vec3::new(0.3f32, 0f32, 2f32),
+        &MeshMaterials::Uniform(Material::PURPLE),
+        &Transform::from_translation(Vec3::new(-0.3f32, 0f32, -2f32)),
+        // The goblet ships with one normal per face, which would keep it faceted.
+        &MeshShading {
+            normals: NormalSource::Generated,
+            ..Default::default()
+        },
     );
 
     add_triangulated_mesh(
         &seashell,
         &mut objects,
-        Material::IVORY,
-        Vec3::new(-0.1f32, 0.5f32, 1f32),
+        &MeshMaterials::Uniform(Material::IVORY),
+        &Transform::from_translation(Vec3::new(0.1f32, -0.5f32, -1f32)),
+        &MeshShading::default(),
     );
 
     add_triangulated_mesh(
         &boat,
         &mut objects,
-        Material::ORANGE,
-        Vec3::new(1f32, 0.55f32, 1f32),
+        &MeshMaterials::Uniform(Material::ORANGE),
+        &Transform::from_translation(Vec3::new(-1f32, -0.55f32, -1f32)),
+        &MeshShading::default(),
     );
 
-    lights.push(Light {
-        position: Vec3::new(-3f32, 4f32, -1f32),
-        intensity: 1.5,
-    });
-    lights.push(Light {
-        position: Vec3::new(2f32, 2f32, -1f32),
-        intensity: 1.5,
-    });
+    lights.push(Light::point(Vec3::new(-3f32, 4f32, -1f32), 1.5));
+    lights.push(Light::point(Vec3::new(2f32, 2f32, -1f32), 1.5));
 
-    return Scene {
+    return Ok(Scene {
         lights,
         objects,
         background: None,
-    };
+        camera: Camera::default(),
+        bvh: None,
+        emitters: Emitters::default(),
+    });
+}
+
+/// Materials given to the groups of an OBJ mesh.
+pub enum MeshMaterials {
+    /// One material for the whole mesh, regardless of the materials its groups name.
+    Uniform(Material),
+    /// Materials looked up by the name each group uses, usually read from the mesh's `mtllib`.
+    /// Groups that name no material, or one that is missing, get `fallback`.
+    Library {
+        materials: HashMap<String, Material>,
+        fallback: Material,
+    },
+}
+
+impl MeshMaterials {
+    fn get(&self, name: Option<&String>) -> &Material {
+        match self {
+            MeshMaterials::Uniform(material) => return material,
+            MeshMaterials::Library {
+                materials,
+                fallback,
+            } => {
+                return name
+                    .and_then(|name| materials.get(name))
+                    .unwrap_or(fallback);
+            }
+        }
+    }
 }
 
 pub fn add_triangulated_mesh(
     model: &ObjSet,
     objects: &mut Vec<Box<dyn Traceable>>,
-    material: Material,
-    offset: Vec3,
+    materials: &MeshMaterials,
+    transform: &Transform,
+    shading: &MeshShading,
 ) {
+    // Callers check with `validate_matrix` that the transform can be inverted.
+    let matrix = transform.matrix();
+    // Normals have to be transformed by the inverse transpose to stay perpendicular under
+    // non-uniform scaling.
+    let normal_matrix = matrix.inverse().transpose();
     model.objects.iter().for_each(|object| {
+        let vertex = |index: usize| {
+            let vertex = object.vertices[index];
+            matrix.transform_point3(Vec3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32))
+        };
+        let normal = |normal: Vec3| normal_matrix.transform_vector3(normal).normalize();
+        let generated_normals = match shading.normals {
+            NormalSource::Flat => Vec::new(),
+            _ => generate_smooth_normals(object, shading.crease_angle),
+        };
+        let mut face_index = 0;
         object.geometry.iter().for_each(|geo| {
+            let material = materials.get(geo.material_name.as_ref());
             geo.shapes.iter().for_each(|shape| {
                 if let Primitive::Triangle(x, y, z) = shape.primitive {
+                    let normals = match (shading.normals, x.2, y.2, z.2) {
+                        (NormalSource::Flat, ..) => None,
+                        (NormalSource::Auto, Some(n0), Some(n1), Some(n2)) => {
+                            Some([n0, n1, n2].map(|index| {
+                                let n = object.normals[index];
+                                normal(Vec3::new(n.x as f32, n.y as f32, n.z as f32))
+                            }))
+                        }
+                        _ => generated_normals[face_index].map(|normals| normals.map(normal)),
+                    };
+                    let uvs = match (x.1, y.1, z.1) {
+                        (Some(t0), Some(t1), Some(t2)) => Some([t0, t1, t2].map(|index| {
+                            let uv = object.tex_vertices[index];
+                            Vec2::new(uv.u as f32, uv.v as f32)
+                        })),
+                        _ => None,
+                    };
                     objects.push(Box::new(Triangle {
-                        v0: Vec3::new(
-                            object.vertices[x.0].x as f32 - offset.x,
-                            object.vertices[x.0].y as f32 - offset.y,
-                            object.vertices[x.0].z as f32 - offset.z,
-                        ),
-                        v1: Vec3::new(
-                            object.vertices[y.0].x as f32 - offset.x,
-                            object.vertices[y.0].y as f32 - offset.y,
-                            object.vertices[y.0].z as f32 - offset.z,
-                        ),
-                        v2: Vec3::new(
-                            object.vertices[z.0].x as f32 - offset.x,
-                            object.vertices[z.0].y as f32 - offset.y,
-                            object.vertices[z.0].z as f32 - offset.z,
-                        ),
-                        material,
+                        v0: vertex(x.0),
+                        v1: vertex(y.0),
+                        v2: vertex(z.0),
+                        normals,
+                        uvs,
+                        material: material.clone(),
                     }));
+                    face_index += 1;
                 }
             });
         });
     });
 }
+
+/// Builds per-corner vertex normals for every triangle of `object`, in model space. Each corner
+/// averages the area weighted normals of the faces around its vertex, skipping faces that meet
+/// the triangle at more than `crease_angle` degrees so that hard edges stay sharp. Corners whose
+/// faces cancel out, as on both sides of a thin sheet, keep the normal of their own face.
+fn generate_smooth_normals(object: &obj::Object, crease_angle: f32) -> Vec<Option<[Vec3; 3]>> {
+    let position = |index: usize| {
+        let vertex = object.vertices[index];
+        Vec3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32)
+    };
+    let faces: Vec<([usize; 3], Vec3)> = object
+        .geometry
+        .iter()
+        .flat_map(|geo| geo.shapes.iter())
+        .filter_map(|shape| match shape.primitive {
+            Primitive::Triangle(x, y, z) => {
+                let (v0, v1, v2) = (position(x.0), position(y.0), position(z.0));
+                // Left unnormalized, so that larger faces carry more weight.
+                Some(([x.0, y.0, z.0], (v1 - v0).cross(v2 - v0)))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut adjacent_faces: Vec<Vec<usize>> = vec![Vec::new(); object.vertices.len()];
+    for (face_index, (indices, _)) in faces.iter().enumerate() {
+        for &index in indices {
+            adjacent_faces[index].push(face_index);
+        }
+    }
+
+    let cos_crease = crease_angle.to_radians().cos();
+    return faces
+        .iter()
+        .map(|(indices, face_normal)| {
+            let face_direction = face_normal.try_normalize()?;
+            Some(indices.map(|index| {
+                adjacent_faces[index]
+                    .iter()
+                    .map(|&adjacent| faces[adjacent].1)
+                    .filter(|adjacent_normal| {
+                        adjacent_normal
+                            .try_normalize()
+                            .is_some_and(|direction| direction.dot(face_direction) >= cos_crease)
+                    })
+                    .sum::<Vec3>()
+                    .try_normalize()
+                    .unwrap_or(face_direction)
+            }))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec4;
+    use serde_json::json;
+
+    /// Resolves the material of `object` against the built-in materials and `materials`.
+    fn resolve_material(materials: Value, object: Value) -> (Option<Material>, Vec<SceneError>) {
+        let mut errors = Vec::new();
+        let materials_json = materials.as_object().cloned().unwrap_or_default();
+        let library = material_library(&materials_json, Path::new(""), &mut errors);
+        let material = material_field(&object, "objects[0]", Path::new(""), &library, &mut errors);
+        return (material, errors);
+    }
+
+    fn albedo(material: Option<Material>) -> Vec4 {
+        return match material {
+            Some(Material::Phong(material)) => material.albedo,
+            other => panic!("expected a Phong material, got {other:?}"),
+        };
+    }
+
+    fn matte(albedo: [f32; 4]) -> Value {
+        return json!({
+            "diffuse_color": { "r": 200, "g": 40, "b": 40 },
+            "albedo": albedo,
+            "specular_exponent": 10,
+            "refractive_index": 1
+        });
+    }
+
+    #[test]
+    fn mesh_groups_get_the_material_they_name() {
+        let model = obj::parse(
+            "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+usemtl red
+f 1 2 3
+usemtl unknown
+f 1 2 3
+usemtl red
+f 1 2 3
+",
+        )
+        .unwrap();
+        let materials = MeshMaterials::Library {
+            materials: HashMap::from([(String::from("red"), Material::RED)]),
+            fallback: Material::IVORY,
+        };
+        let mut objects = Vec::new();
+        add_triangulated_mesh(
+            &model,
+            &mut objects,
+            &materials,
+            &Transform::default(),
+            &MeshShading::default(),
+        );
+        let assigned: Vec<String> = objects
+            .iter()
+            .map(|object| format!("{:?}", object.material()))
+            .collect();
+        let (red, ivory) = (
+            format!("{:?}", Material::RED),
+            format!("{:?}", Material::IVORY),
+        );
+        assert_eq!(assigned, [&ivory, &red, &ivory, &red].map(String::as_str));
+    }
+
+    #[test]
+    fn missing_material_libraries_leave_meshes_gray() {
+        let mut errors = Vec::new();
+        let library = load_material_library(
+            Path::new("does/not/exist.mtl"),
+            "objects[0].path",
+            &mut errors,
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        let Some(MeshMaterials::Library { materials, .. }) = library else {
+            panic!("expected a library");
+        };
+        assert!(materials.is_empty());
+    }
+
+    /// Errors found in a scene file with the given contents, written to a temporary file named
+    /// after `name`.
+    fn scene_errors(name: &str, scene: Value) -> Vec<SceneError> {
+        let path = std::env::temp_dir().join(format!("ferox-{name}-{}.json", std::process::id()));
+        fs::write(&path, scene.to_string()).unwrap();
+        let result = parse_scene_json(&path);
+        fs::remove_file(&path).unwrap();
+        return match result {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors,
+        };
+    }
+
+    #[test]
+    fn nested_errors_name_the_full_path() {
+        let sphere = |material: Value| {
+            json!({
+                "lights": [],
+                "objects": [
+                    { "name": "sphere", "center": [0, 0, -3], "radius": 1, "material": "ivory" },
+                    { "name": "sphere", "center": [0, 0, -3], "radius": 1, "material": material }
+                ]
+            })
+        };
+        let mut wrong_type = matte([0.6, 0.3, 0.1, 0.0]);
+        wrong_type["albedo"][1] = json!("high");
+        let errors = scene_errors("wrong-type", sphere(wrong_type));
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [SceneError::WrongType { field, .. }] if field == "objects[1].material.albedo[1]"
+            ),
+            "{errors:?}"
+        );
+
+        let mut missing = matte([0.6, 0.3, 0.1, 0.0]);
+        missing.as_object_mut().unwrap().remove("albedo");
+        let errors = scene_errors("missing-member", sphere(missing));
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [SceneError::MissingField { field }] if field == "objects[1].material.albedo"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_objects_and_missing_meshes_are_reported() {
+        let errors = scene_errors(
+            "unknown-objects",
+            json!({
+                "lights": [],
+                "objects": [
+                    { "name": "torus", "radius": 1 },
+                    { "name": "mesh", "path": "does/not/exist.obj", "material": "ivory" }
+                ]
+            }),
+        );
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [
+                    SceneError::UnknownObject { field, name },
+                    SceneError::BadMeshPath { field: Some(mesh_field), .. },
+                ] if field == "objects[0].name" && name == "torus" && mesh_field == "objects[1].path"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    /// Generated normals of the first object of an OBJ file, next to the corners of each face.
+    fn smooth_normals(contents: &str, crease_angle: f32) -> Vec<([Vec3; 3], [Vec3; 3])> {
+        let model = obj::parse(triangulate_faces(contents).0).unwrap();
+        let object = &model.objects[0];
+        let position = |index: usize| {
+            let vertex = object.vertices[index];
+            Vec3::new(vertex.x as f32, vertex.y as f32, vertex.z as f32)
+        };
+        let shapes = object.geometry.iter().flat_map(|geometry| &geometry.shapes);
+        return generate_smooth_normals(object, crease_angle)
+            .into_iter()
+            .zip(shapes)
+            .map(|(normals, shape)| {
+                let Primitive::Triangle(a, b, c) = shape.primitive else {
+                    panic!("expected only triangles");
+                };
+                (normals.unwrap(), [a.0, b.0, c.0].map(position))
+            })
+            .collect();
+    }
+
+    fn face_normal([a, b, c]: [Vec3; 3]) -> Vec3 {
+        return (b - a).cross(c - a).normalize();
+    }
+
+    #[test]
+    fn cubes_keep_flat_faces() {
+        let cube = "\
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+f 1 4 3 2
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+";
+        let faces = smooth_normals(cube, MeshShading::default().crease_angle);
+        assert_eq!(faces.len(), 12);
+        for (normals, corners) in faces {
+            assert_eq!(normals, [face_normal(corners); 3]);
+        }
+    }
+
+    #[test]
+    fn rounded_meshes_average_the_faces_around_a_corner() {
+        let octahedron = "\
+v 1 0 0
+v -1 0 0
+v 0 1 0
+v 0 -1 0
+v 0 0 1
+v 0 0 -1
+f 1 3 5
+f 3 2 5
+f 2 4 5
+f 4 1 5
+f 3 1 6
+f 2 3 6
+f 4 2 6
+f 1 4 6
+";
+        // The four faces around a corner of an octahedron lie up to 110 degrees apart, and
+        // average out to the direction of the corner.
+        for (normals, corners) in smooth_normals(octahedron, 120f32) {
+            for (normal, corner) in normals.into_iter().zip(corners) {
+                assert!(normal.abs_diff_eq(corner, 1e-6), "{normal} {corner}");
+            }
+        }
+
+        // Both sides of a sheet cancel out, which leaves each side its own normal.
+        let sheet = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+f 1 3 2
+";
+        for (normals, corners) in smooth_normals(sheet, 180f32) {
+            assert_eq!(normals, [face_normal(corners); 3]);
+        }
+    }
+
+    /// Triangles of the OBJ file once its faces have been triangulated.
+    fn triangulated(contents: &str) -> (ObjSet, SkippedPrimitives) {
+        let (contents, skipped) = triangulate_faces(contents);
+        return (obj::parse(contents).unwrap(), skipped);
+    }
+
+    fn total_area(model: &ObjSet) -> f64 {
+        let object = &model.objects[0];
+        let mut area = 0f64;
+        for shape in object.geometry.iter().flat_map(|geometry| &geometry.shapes) {
+            let Primitive::Triangle(a, b, c) = shape.primitive else {
+                panic!("expected only triangles");
+            };
+            let [a, b, c] = [a, b, c].map(|corner| {
+                let vertex = object.vertices[corner.0];
+                glam::DVec3::new(vertex.x, vertex.y, vertex.z)
+            });
+            area += (b - a).cross(c - a).length() / 2f64;
+        }
+        return area;
+    }
+
+    #[test]
+    fn quads_with_relative_indices_are_split() {
+        let (model, skipped) = triangulated(
+            "\
+v 0 0 0
+v 2 0 0
+v 2 1 0
+v 0 1 0
+vn 0 0 1
+f -4//-1 -3//-1 -2//-1 -1//-1
+f -4 -3 -2
+",
+        );
+        assert_eq!(skipped, SkippedPrimitives::default());
+        let shapes = &model.objects[0].geometry[0].shapes;
+        assert_eq!(shapes.len(), 3);
+        assert!(matches!(
+            shapes[0].primitive,
+            Primitive::Triangle((_, None, Some(0)), ..)
+        ));
+        assert!((total_area(&model) - 3f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quads_keep_their_texture_coordinates_and_normals() {
+        let contents = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 4/4/1
+";
+        let (rewritten, _) = triangulate_faces(contents);
+        for face in rewritten.lines().filter(|line| line.starts_with("f ")) {
+            for corner in face.split_whitespace().skip(1) {
+                let [position, uv, normal] = corner.split('/').collect::<Vec<_>>()[..] else {
+                    panic!("{face} lost part of a corner");
+                };
+                assert_eq!(position, uv);
+                assert_eq!(normal, "1");
+            }
+        }
+        let (model, _) = triangulated(contents);
+        let object = &model.objects[0];
+        for shape in &object.geometry[0].shapes {
+            let Primitive::Triangle(a, b, c) = shape.primitive else {
+                panic!("expected only triangles");
+            };
+            for (position, uv, normal) in [a, b, c] {
+                let vertex = object.vertices[position];
+                let uv = object.tex_vertices[uv.unwrap()];
+                assert_eq!((vertex.x, vertex.y), (uv.u, uv.v));
+                assert_eq!(normal, Some(0));
+            }
+        }
+        assert!((total_area(&model) - 1f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn points_and_lines_are_counted_and_left_out() {
+        let (rewritten, skipped) = triangulate_faces(
+            "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+p 1 2 3
+p 1
+l 1 2 3
+f 1 2
+f 3
+f 1 2 3
+",
+        );
+        assert_eq!(
+            skipped,
+            SkippedPrimitives {
+                points: 5,
+                lines: 3
+            }
+        );
+        let kept: Vec<&str> = rewritten
+            .lines()
+            .filter(|line| !line.starts_with("v "))
+            .collect();
+        assert_eq!(kept, ["f 1 2 3"]);
+    }
+
+    #[test]
+    fn scene_materials_are_found_by_name() {
+        let materials = json!({ "clay": matte([0.9, 0.1, 0.0, 0.0]) });
+        let (material, errors) = resolve_material(materials, json!({ "material": "clay" }));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(albedo(material), Vec4::new(0.9, 0.1, 0f32, 0f32));
+    }
+
+    #[test]
+    fn built_in_materials_are_found_by_name() {
+        for (name, expected) in Material::PREDEFINED {
+            let (material, errors) = resolve_material(json!({}), json!({ "material": name }));
+            assert!(errors.is_empty(), "{errors:?}");
+            assert_eq!(albedo(material), albedo(Some(expected)), "{name}");
+        }
+    }
+
+    #[test]
+    fn scene_materials_replace_built_in_ones() {
+        let materials = json!({ "ivory": matte([0.2, 0.8, 0.0, 0.0]) });
+        let (material, errors) = resolve_material(materials, json!({ "material": "ivory" }));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(albedo(material), Vec4::new(0.2, 0.8, 0f32, 0f32));
+    }
+
+    #[test]
+    fn undefined_material_names_are_reported() {
+        let (material, errors) = resolve_material(json!({}), json!({ "material": "marble" }));
+        assert!(material.is_none());
+        assert!(matches!(
+            errors.as_slice(),
+            [SceneError::UndefinedName { field, kind: "material", name }]
+                if field == "objects[0].material" && name == "marble"
+        ));
+    }
+}
@@ -1,10 +1,41 @@
+mod bvh;
+mod error;
+mod gltf;
 mod init;
+mod mtl;
+mod output;
+mod pathtracer;
+mod pbr;
+mod polygon;
 mod raytracer;
+mod sampling;
 mod structures;
+mod texture;
+
+use std::process;
 
 use init::initialize;
 
 fn main() {
-    let execution_context = initialize();
-    raytracer::render(execution_context.scene, &execution_context.output);
+    let execution_context = match initialize() {
+        Ok(execution_context) => execution_context,
+        Err(errors) => {
+            eprintln!("Unable to load scene:");
+            for error in errors {
+                eprintln!("  {error}");
+            }
+            process::exit(1);
+        }
+    };
+    let frame_buffer = raytracer::render(&execution_context.scene, &execution_context.settings);
+    let output = &execution_context.output;
+    if let Err(error) = output::save(
+        &frame_buffer,
+        &execution_context.settings.tone_mapping,
+        output,
+    ) {
+        eprintln!("Unable to save {output}: {error}");
+        process::exit(1);
+    }
+    println!("Image has been rendered and saved to {output}!");
 }
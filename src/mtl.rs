@@ -0,0 +1,280 @@
+#![allow(clippy::needless_return)]
+
+use std::{path::PathBuf, sync::Arc};
+
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::structures::{Color, Emission, Material, PhongMaterial};
+use crate::texture::{ColorInput, ImageTexture, ScalarInput, Texture};
+
+/// A material of a Wavefront `.mtl` library, as far as ferox can represent it. Colors are linear.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: Vec3,
+    /// `Ks`
+    pub specular: Vec3,
+    /// `Ns`
+    pub specular_exponent: f32,
+    /// `Ni`
+    pub refractive_index: f32,
+    /// `d`, or one minus `Tr`.
+    pub opacity: f32,
+    /// `Ke`
+    pub emission: Vec3,
+    /// `map_Kd`, relative to the library. The image is multiplied by `diffuse`.
+    pub diffuse_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    /// A material with the defaults of the format, used for statements that are left out.
+    pub fn new(name: &str) -> MtlMaterial {
+        return MtlMaterial {
+            name: String::from(name),
+            diffuse: Vec3::splat(0.8),
+            specular: Vec3::ZERO,
+            specular_exponent: 0f32,
+            refractive_index: 1f32,
+            opacity: 1f32,
+            emission: Vec3::ZERO,
+            diffuse_map: None,
+        };
+    }
+
+    /// Converts to a Phong material. The opacity splits the albedo between diffuse and
+    /// refractive scattering, and the strongest channel of `Ks` sets the specular weight. The
+    /// `map_Kd` image still has to be loaded and then tinted with `tint_diffuse_map`.
+    pub fn to_material(&self) -> Material {
+        let diffuse_color = match &self.diffuse_map {
+            Some(path) => ColorInput::Texture(Texture::Image(ImageTexture {
+                path: path.clone(),
+                wrap: Default::default(),
+                filter: Default::default(),
+                scale: Vec2::ONE,
+                image: None,
+            })),
+            None => ColorInput::Constant(Color::from_linear(self.diffuse)),
+        };
+        let opacity = self.opacity.clamp(0f32, 1f32);
+        // Emission brighter than white is kept in the strength.
+        let strength = self.emission.max_element();
+        let emission = match strength > 0f32 {
+            true => Some(Emission {
+                color: ColorInput::Constant(Color::from_linear(self.emission / strength)),
+                strength,
+            }),
+            false => None,
+        };
+        return Material::Phong(PhongMaterial {
+            diffuse_color,
+            albedo: Vec4::new(
+                opacity,
+                self.specular.max_element().clamp(0f32, 1f32),
+                0f32,
+                1f32 - opacity,
+            ),
+            specular_exponent: ScalarInput::Constant(self.specular_exponent.max(1f32)),
+            refractive_index: ScalarInput::Constant(self.refractive_index),
+            absorption: None,
+            normal_map: None,
+            bump_map: None,
+            emission,
+        });
+    }
+
+    /// Multiplies the loaded `map_Kd` image of `material`, converted from this one, by `Kd`, as
+    /// the two are combined by the format. The factor is baked into the image, like the factors
+    /// of glTF textures.
+    pub fn tint_diffuse_map(&self, material: &mut Material) {
+        let Material::Phong(PhongMaterial {
+            diffuse_color: ColorInput::Texture(Texture::Image(texture)),
+            ..
+        }) = material
+        else {
+            return;
+        };
+        let Some(image) = &mut texture.image else {
+            return;
+        };
+        for pixel in Arc::make_mut(image).pixels_mut() {
+            pixel.0 = (Vec3::from(pixel.0) * self.diffuse).to_array();
+        }
+    }
+}
+
+/// Reads the materials of an `.mtl` file. Statements that ferox has no use for are skipped.
+pub fn parse(contents: &str) -> Result<Vec<MtlMaterial>, String> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let (keyword, rest) = split_token(line);
+        if keyword.is_empty() {
+            continue;
+        }
+        let tokens = rest.split_whitespace();
+        let arguments: Vec<&str> = tokens.collect();
+        let error = |message: String| format!("line {}: {message}", index + 1);
+
+        if keyword == "newmtl" {
+            match arguments.first() {
+                Some(name) => materials.push(MtlMaterial::new(name)),
+                None => return Err(error(String::from("newmtl without a name"))),
+            }
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            continue;
+        };
+        // Options such as `-s 2 2 1` come before the values, so these are read from the end.
+        let number = || {
+            let argument = arguments
+                .last()
+                .ok_or_else(|| error(format!("{keyword} without a value")))?;
+            return argument
+                .parse::<f32>()
+                .map_err(|_| error(format!("{keyword} expects a number, got \"{argument}\"")));
+        };
+        let color = || {
+            let values = arguments
+                .iter()
+                .map(|argument| argument.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|_| error(format!("{keyword} expects an r g b color")))?;
+            return match values.as_slice() {
+                [value] => Ok(Vec3::splat(*value)),
+                [r, g, b] => Ok(Vec3::new(*r, *g, *b)),
+                _ => Err(error(format!("{keyword} expects an r g b color"))),
+            };
+        };
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ke" => material.emission = color()?,
+            "Ns" => material.specular_exponent = number()?,
+            "Ni" => material.refractive_index = number()?,
+            "d" => material.opacity = number()?,
+            "Tr" => material.opacity = 1f32 - number()?,
+            "map_Kd" => match map_path(rest) {
+                Some(path) => material.diffuse_map = Some(PathBuf::from(path)),
+                None => return Err(error(String::from("map_Kd without an image"))),
+            },
+            _ => {}
+        }
+    }
+    return Ok(materials);
+}
+
+/// Image file of a texture map statement, which follows its options, e.g. `-s 2 2 1 old oak.png`.
+/// The file is the rest of the line, so that its name may contain spaces.
+fn map_path(arguments: &str) -> Option<&str> {
+    let mut rest = arguments.trim();
+    while let Some(option) = rest.strip_prefix('-') {
+        let (name, after) = split_token(option);
+        rest = after;
+        // Options take a single value, except for `-mm` with two and the offset, scale and
+        // turbulence options with one to three numbers.
+        let (required, most) = match name {
+            "o" | "s" | "t" => (1, 3),
+            "mm" => (2, 2),
+            _ => (1, 1),
+        };
+        for count in 0..most {
+            let (value, after) = split_token(rest);
+            if count >= required && value.parse::<f32>().is_err() {
+                break;
+            }
+            rest = after;
+        }
+    }
+    return match rest.is_empty() {
+        true => None,
+        false => Some(rest),
+    };
+}
+
+/// First whitespace separated token of `text` and the text after it, without leading whitespace.
+fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    return (&text[..end], text[end..].trim_start());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn materials_read_the_supported_statements_in_any_order() {
+        let library = "\
+# Exported by hand
+newmtl Hull
+Ns 250.0
+Kd 0.5 0.25 0.125
+Ks 0.4 0.5 0.3
+illum 2
+map_Kd -s 2 2 1 -clamp on textures/old oak.png
+
+newmtl Window
+Tr 0.75
+Ni 1.45
+Ke 2 1 0
+";
+        let materials = parse(library).unwrap();
+        assert_eq!(materials.len(), 2);
+        let hull = &materials[0];
+        assert_eq!(hull.name, "Hull");
+        assert_eq!(hull.diffuse, Vec3::new(0.5, 0.25, 0.125));
+        assert_eq!(hull.specular_exponent, 250f32);
+        assert_eq!(
+            hull.diffuse_map,
+            Some(PathBuf::from("textures/old oak.png"))
+        );
+        let window = &materials[1];
+        assert_eq!(window.diffuse, Vec3::splat(0.8));
+        assert_eq!(window.opacity, 0.25);
+        assert_eq!(window.refractive_index, 1.45);
+
+        let Material::Phong(window) = window.to_material() else {
+            panic!("MTL materials are Phong materials");
+        };
+        assert_eq!(window.albedo, Vec4::new(0.25, 0f32, 0f32, 0.75));
+        assert_eq!(window.emission.unwrap().strength, 2f32);
+
+        assert!(
+            parse("newmtl Broken\nKd 1 zero 0\n")
+                .unwrap_err()
+                .starts_with("line 2")
+        );
+    }
+
+    #[test]
+    fn diffuse_maps_are_tinted_by_the_diffuse_color() {
+        let mut material = MtlMaterial::new("Tinted");
+        material.diffuse = Vec3::new(0.5, 1f32, 0.25);
+        material.diffuse_map = Some(PathBuf::from("tile.png"));
+        let mut converted = material.to_material();
+        let Material::Phong(PhongMaterial {
+            diffuse_color: ColorInput::Texture(Texture::Image(texture)),
+            ..
+        }) = &mut converted
+        else {
+            panic!("map_Kd becomes an image texture");
+        };
+        texture.image = Some(Arc::new(image::Rgb32FImage::from_pixel(
+            1,
+            1,
+            image::Rgb([0.8, 0.4, 1f32]),
+        )));
+        material.tint_diffuse_map(&mut converted);
+        let Material::Phong(PhongMaterial {
+            diffuse_color: ColorInput::Texture(Texture::Image(texture)),
+            ..
+        }) = &converted
+        else {
+            unreachable!();
+        };
+        let pixel = texture.image.as_ref().unwrap().get_pixel(0, 0).0;
+        assert_eq!(Vec3::from(pixel), Vec3::new(0.4, 0.4, 0.25));
+    }
+}
@@ -0,0 +1,172 @@
+#![allow(clippy::needless_return)]
+
+use clap::ValueEnum;
+use glam::Vec3;
+use image::{ImageFormat, ImageResult, Rgb, Rgb32FImage, RgbImage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMapOperator {
+    /// Clips anything brighter than white.
+    Clamp,
+    /// `x / (1 + x)`, which compresses highlights without ever reaching white.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// `1 - e^-x`, mimicking the response of film to exposure.
+    Exposure,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, applied before the operator.
+    pub exposure: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0f32,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Maps linear radiance to a displayable linear value in `[0, 1]`.
+    pub fn apply(&self, radiance: Vec3) -> Vec3 {
+        let x = radiance.max(Vec3::ZERO) * 2f32.powf(self.exposure);
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => x,
+            ToneMapOperator::Reinhard => x / (Vec3::ONE + x),
+            ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapOperator::Exposure => Vec3::ONE - (-x).exp(),
+        };
+        return mapped.clamp(Vec3::ZERO, Vec3::ONE);
+    }
+
+    /// Tone maps and sRGB encodes a linear framebuffer into an 8-bit image.
+    pub fn display_image(&self, frame_buffer: &Rgb32FImage) -> RgbImage {
+        return RgbImage::from_fn(frame_buffer.width(), frame_buffer.height(), |x, y| {
+            let radiance = Vec3::from_array(frame_buffer.get_pixel(x, y).0);
+            let encoded = self.apply(radiance).to_array().map(linear_to_srgb);
+            Rgb(encoded.map(|channel| (channel * 255f32).round() as u8))
+        });
+    }
+}
+
+/// Writes the rendered radiance to `output`, picking the format from its extension. OpenEXR and
+/// Radiance HDR files receive the untouched linear values for compositing, while every other
+/// format is tone mapped and sRGB encoded.
+pub fn save(
+    frame_buffer: &Rgb32FImage,
+    tone_mapping: &ToneMapping,
+    output: &str,
+) -> ImageResult<()> {
+    return match ImageFormat::from_path(output) {
+        Ok(ImageFormat::OpenExr | ImageFormat::Hdr) => frame_buffer.save(output),
+        _ => tone_mapping.display_image(frame_buffer).save(output),
+    };
+}
+
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        return 12.92 * linear;
+    }
+    return 1.055 * linear.powf(1f32 / 2.4) - 0.055;
+}
+
+pub fn srgb_to_linear(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        return encoded / 12.92;
+    }
+    return ((encoded + 0.055) / 1.055).powf(2.4);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(operator: ToneMapOperator, exposure: f32) -> ToneMapping {
+        return ToneMapping { operator, exposure };
+    }
+
+    #[test]
+    fn operators_map_radiance_as_documented() {
+        let reinhard = mapping(ToneMapOperator::Reinhard, 0f32);
+        assert_eq!(reinhard.apply(Vec3::ONE), Vec3::splat(0.5));
+        let aces = mapping(ToneMapOperator::Aces, 0f32);
+        assert_eq!(aces.apply(Vec3::ZERO), Vec3::ZERO);
+        let clamp = mapping(ToneMapOperator::Clamp, 0f32);
+        assert_eq!(
+            clamp.apply(Vec3::new(-1f32, 0.25, 4f32)),
+            Vec3::new(0f32, 0.25, 1f32)
+        );
+
+        let exposure = mapping(ToneMapOperator::Exposure, 0f32);
+        let mut previous = 0f32;
+        for radiance in [0.1, 0.5, 1f32, 2f32, 8f32] {
+            let mapped = exposure.apply(Vec3::splat(radiance)).x;
+            assert!(mapped > previous && mapped < 1f32, "{radiance}: {mapped}");
+            previous = mapped;
+        }
+    }
+
+    #[test]
+    fn each_stop_of_exposure_doubles_the_input() {
+        let radiance = Vec3::new(0.05, 0.1, 0.2);
+        for operator in [ToneMapOperator::Clamp, ToneMapOperator::Reinhard] {
+            let brighter = mapping(operator, 1f32).apply(radiance);
+            let doubled = mapping(operator, 0f32).apply(radiance * 2f32);
+            assert!(brighter.abs_diff_eq(doubled, 1e-6), "{operator:?}");
+        }
+    }
+
+    #[test]
+    fn srgb_encoding_round_trips_across_the_knees() {
+        for linear in [0f32, 0.001, 0.0031308, 0.0032, 0.2, 1f32] {
+            let round_trip = srgb_to_linear(linear_to_srgb(linear));
+            assert!((round_trip - linear).abs() < 1e-6, "{linear}: {round_trip}");
+        }
+        for encoded in [0.04, 0.04045, 0.041, 0.5] {
+            let round_trip = linear_to_srgb(srgb_to_linear(encoded));
+            assert!(
+                (round_trip - encoded).abs() < 1e-6,
+                "{encoded}: {round_trip}"
+            );
+        }
+        // Both pieces of the curve meet at the knee.
+        assert!((linear_to_srgb(0.0031308) - 0.04045).abs() < 1e-5);
+    }
+
+    #[test]
+    fn display_images_are_tone_mapped_and_encoded() {
+        let frame_buffer = Rgb32FImage::from_pixel(1, 1, Rgb([4f32, 0.2, 0f32]));
+        let image = ToneMapping::default().display_image(&frame_buffer);
+        let expected = (linear_to_srgb(0.2) * 255f32).round() as u8;
+        assert_eq!(image.get_pixel(0, 0).0, [255, expected, 0]);
+    }
+    #[test]
+    fn high_dynamic_range_formats_keep_the_radiance() {
+        // Exactly representable in the shared exponent encoding of Radiance HDR files.
+        let radiance = [4f32, 2f32, 0.5];
+        let frame_buffer = Rgb32FImage::from_pixel(1, 1, Rgb(radiance));
+        let directory = std::env::temp_dir();
+        let id = std::process::id();
+        for extension in ["exr", "hdr", "png"] {
+            let path = directory.join(format!("ferox-save-{id}.{extension}"));
+            let output = path.to_str().unwrap();
+            save(&frame_buffer, &ToneMapping::default(), output).unwrap();
+            let read = image::open(&path).unwrap().into_rgb32f();
+            std::fs::remove_file(&path).unwrap();
+            let expected = match extension {
+                "png" => [1f32, 1f32, (linear_to_srgb(0.5) * 255f32).round() / 255f32],
+                _ => radiance,
+            };
+            assert_eq!(read.get_pixel(0, 0).0, expected, "{extension}");
+        }
+    }
+}
@@ -0,0 +1,461 @@
+#![allow(clippy::needless_return)]
+
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::pbr::PbrSurface;
+use crate::raytracer::{
+    background_color, crossed_medium, dielectric_reflectance, reflection_angle, refraction_angle,
+    scene_intersect,
+};
+use crate::sampling::{Rng, cosine_hemisphere, phong_lobe};
+use crate::structures::{EPSILON, Intersection, LightSample, Material, PhongMaterial, Scene};
+
+/// Number of bounces that are always traced before Russian roulette may end a path.
+const ROULETTE_DEPTH: u32 = 3;
+/// Hard limit on path length, which only paths trapped between mirrors ever reach.
+const MAX_DEPTH: u32 = 64;
+
+/// Estimates the radiance arriving at `origin` from `direction` with a single random path.
+/// Lights are sampled explicitly at every diffuse or glossy vertex (next-event estimation), and
+/// the environment is picked up when a path escapes the scene. Emissive objects are sampled
+/// explicitly only for the diffuse lobes. Paths pick up their emission when they hit them after
+/// any other kind of bounce, so that no light is counted twice.
+pub fn trace_path(scene: &Scene, origin: &Vec3, direction: &Vec3, rng: &mut Rng) -> Vec3 {
+    let mut radiance = Vec3::ZERO;
+    let mut throughput = Vec3::ONE;
+    let mut origin = *origin;
+    let mut direction = direction.normalize();
+    let mut count_emission = true;
+    // Object the path is travelling through, entered and left by refraction.
+    let mut medium: Option<&Material> = None;
+
+    for depth in 0..MAX_DEPTH {
+        let Some(intersection) = scene_intersect(scene, &origin, &direction) else {
+            radiance += throughput * background_color(&direction, scene);
+            break;
+        };
+        let hit = intersection.point;
+        if let Some(medium) = medium {
+            throughput *= medium.transmittance((hit - origin).length());
+        }
+        if count_emission {
+            radiance += throughput * intersection.material.emitted_radiance(&intersection);
+        }
+        // Shading happens on the side the ray arrived from, which is the inside for refractive
+        // objects.
+        let shading_normal = intersection.material.shading_normal(&intersection);
+        let (geometric_normal, normal) = match direction.dot(intersection.normal) < 0f32 {
+            true => (intersection.normal, shading_normal),
+            false => (-intersection.normal, -shading_normal),
+        };
+        let bsdf = Bsdf::new(&intersection);
+
+        radiance += throughput * direct_light(scene, &bsdf, &hit, &normal, &direction, rng);
+
+        let Some((next_direction, weight, diffuse)) =
+            bsdf.scatter(&direction, &intersection.normal, &normal, rng)
+        else {
+            break;
+        };
+        throughput *= weight;
+        count_emission = !diffuse;
+        if next_direction.dot(geometric_normal) < 0f32 {
+            medium = crossed_medium(medium, intersection.material);
+        }
+
+        if depth >= ROULETTE_DEPTH {
+            let survival = throughput.max_element().min(0.95);
+            if rng.next_f32() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        origin = hit + geometric_normal * EPSILON * next_direction.dot(geometric_normal).signum();
+        direction = next_direction;
+    }
+
+    return radiance;
+}
+
+/// Light arriving directly from every light source and one sampled emissive object, scattered
+/// toward `-direction`.
+fn direct_light(
+    scene: &Scene,
+    bsdf: &Bsdf,
+    hit: &Vec3,
+    normal: &Vec3,
+    direction: &Vec3,
+    rng: &mut Rng,
+) -> Vec3 {
+    let mut radiance = Vec3::ZERO;
+    if !bsdf.has_smooth_component() {
+        return radiance;
+    }
+    for light in &scene.lights {
+        let u = Vec2::new(rng.next_f32(), rng.next_f32());
+        let Some(light_sample) = light.sample(hit, u) else {
+            continue;
+        };
+        radiance += bsdf.evaluate(direction, normal, &light_sample.direction)
+            * unoccluded_light(scene, hit, normal, &light_sample);
+    }
+    let u = Vec2::new(rng.next_f32(), rng.next_f32());
+    if let Some(light_sample) = scene.sample_emitters(hit, u, rng.next_f32()) {
+        radiance += bsdf.evaluate_diffuse(direction, normal, &light_sample.direction)
+            * unoccluded_light(scene, hit, normal, &light_sample);
+    }
+    return radiance;
+}
+
+/// Light of `light_sample` arriving at `hit` times the cosine to `normal`, or zero when it is
+/// blocked or behind the surface.
+fn unoccluded_light(scene: &Scene, hit: &Vec3, normal: &Vec3, light_sample: &LightSample) -> Vec3 {
+    let cos_theta = light_sample.direction.dot(*normal);
+    if cos_theta <= 0f32 {
+        return Vec3::ZERO;
+    }
+    let shadow_origin = hit + normal * EPSILON;
+    if let Some(shadow_intersection) =
+        scene_intersect(scene, &shadow_origin, &light_sample.direction)
+        && (shadow_intersection.point - shadow_origin).length() < light_sample.distance
+    {
+        return Vec3::ZERO;
+    }
+    return light_sample.radiance * light_sample.attenuation * cos_theta;
+}
+
+/// Scattering functions of the material models at a single point, as seen by the path tracer.
+enum Bsdf {
+    Phong(PhongLobes),
+    Pbr(PbrSurface),
+}
+
+impl Bsdf {
+    fn new(intersection: &Intersection) -> Bsdf {
+        return match intersection.material {
+            Material::Phong(material) => Bsdf::Phong(PhongLobes::new(material, intersection)),
+            Material::Pbr(material) => Bsdf::Pbr(material.surface(intersection)),
+        };
+    }
+
+    /// Whether any part of the BSDF can be lit by a light of zero size.
+    fn has_smooth_component(&self) -> bool {
+        return match self {
+            Bsdf::Phong(lobes) => lobes.has_smooth_component(),
+            Bsdf::Pbr(_) => true,
+        };
+    }
+
+    /// BSDF for light arriving from `light_direction` and leaving along `-direction`.
+    fn evaluate(&self, direction: &Vec3, normal: &Vec3, light_direction: &Vec3) -> Vec3 {
+        return match self {
+            Bsdf::Phong(lobes) => lobes.evaluate(direction, normal, light_direction),
+            Bsdf::Pbr(material) => material.evaluate(&-*direction, normal, light_direction),
+        };
+    }
+
+    /// Diffuse part of `evaluate`.
+    fn evaluate_diffuse(&self, direction: &Vec3, normal: &Vec3, light_direction: &Vec3) -> Vec3 {
+        return match self {
+            Bsdf::Phong(lobes) => lobes.evaluate_diffuse(),
+            Bsdf::Pbr(material) => material.evaluate_diffuse(&-*direction, normal, light_direction),
+        };
+    }
+
+    /// Samples the direction a path continues in, see `PhongLobes::scatter`.
+    fn scatter(
+        &self,
+        direction: &Vec3,
+        geometric_normal: &Vec3,
+        normal: &Vec3,
+        rng: &mut Rng,
+    ) -> Option<(Vec3, Vec3, bool)> {
+        return match self {
+            Bsdf::Phong(lobes) => lobes.scatter(direction, geometric_normal, normal, rng),
+            Bsdf::Pbr(material) => {
+                let u = Vec2::new(rng.next_f32(), rng.next_f32());
+                let entering = direction.dot(*geometric_normal) < 0f32;
+                material.sample(direction, normal, entering, u, rng.next_f32())
+            }
+        };
+    }
+}
+
+/// A `PhongMaterial` read as a mixture of four scattering lobes, weighted by its albedo:
+/// Lambertian diffuse, a normalized Phong glossy lobe, a perfect mirror and perfect refraction.
+/// The built-in materials were tuned for the Whitted integrator and their weights can add up to
+/// more than one, so they are scaled down in that case to keep paths from gaining energy.
+struct PhongLobes {
+    diffuse_color: Vec3,
+    weights: [f32; 4],
+    total: f32,
+    specular_exponent: f32,
+    refractive_index: f32,
+}
+
+impl PhongLobes {
+    fn new(material: &PhongMaterial, intersection: &Intersection) -> PhongLobes {
+        let material = material.surface(intersection);
+        let weights = material.albedo.max(Vec4::ZERO).to_array();
+        let sum: f32 = weights.iter().sum();
+        let scale = 1f32 / sum.max(1f32);
+        return PhongLobes {
+            diffuse_color: material.diffuse_color,
+            weights: weights.map(|weight| weight * scale),
+            total: sum * scale,
+            specular_exponent: material.specular_exponent.max(0f32),
+            refractive_index: material.refractive_index,
+        };
+    }
+
+    /// Whether any lobe can be lit by a light of zero size.
+    fn has_smooth_component(&self) -> bool {
+        return self.weights[0] > 0f32 || self.weights[1] > 0f32;
+    }
+
+    /// BRDF of the diffuse and glossy lobes for light arriving from `light_direction`. The
+    /// mirror and refraction lobes are zero everywhere except along a single direction.
+    fn evaluate(&self, direction: &Vec3, normal: &Vec3, light_direction: &Vec3) -> Vec3 {
+        let diffuse = self.evaluate_diffuse();
+        let reflected = reflection_angle(direction, normal);
+        let glossy = self.weights[1] * (self.specular_exponent + 2f32) / (2f32 * PI)
+            * reflected
+                .dot(*light_direction)
+                .max(0f32)
+                .powf(self.specular_exponent);
+        return diffuse + Vec3::splat(glossy);
+    }
+
+    fn evaluate_diffuse(&self) -> Vec3 {
+        return self.diffuse_color * self.weights[0] / PI;
+    }
+
+    /// Picks a lobe in proportion to its weight and samples a new direction from it. Returns the
+    /// direction together with the BSDF times cosine over the sampling density and whether the
+    /// diffuse lobe was picked, or `None` when the path is absorbed.
+    fn scatter(
+        &self,
+        direction: &Vec3,
+        geometric_normal: &Vec3,
+        normal: &Vec3,
+        rng: &mut Rng,
+    ) -> Option<(Vec3, Vec3, bool)> {
+        if self.total <= 0f32 {
+            return None;
+        }
+        let u = Vec2::new(rng.next_f32(), rng.next_f32());
+        let mut choice = rng.next_f32() * self.total;
+        let mut lobe = 0;
+        while lobe < 3 && choice >= self.weights[lobe] {
+            choice -= self.weights[lobe];
+            lobe += 1;
+        }
+        if self.weights[lobe] <= 0f32 {
+            return None;
+        }
+
+        // Each lobe integrates to its weight, so dividing by the probability of picking it
+        // leaves the total weight.
+        return match lobe {
+            0 => Some((
+                cosine_hemisphere(normal, u),
+                self.diffuse_color * self.total,
+                true,
+            )),
+            1 => {
+                let reflected = reflection_angle(direction, normal);
+                let next_direction = phong_lobe(&reflected, self.specular_exponent, u);
+                let cos_theta = next_direction.dot(*normal);
+                if cos_theta <= 0f32 {
+                    return None;
+                }
+                let weight = self.total * (self.specular_exponent + 2f32)
+                    / (self.specular_exponent + 1f32)
+                    * cos_theta;
+                Some((next_direction, Vec3::splat(weight), false))
+            }
+            2 => Some((
+                reflection_angle(direction, normal),
+                Vec3::splat(self.total),
+                false,
+            )),
+            _ => {
+                // The Fresnel term decides between reflection and refraction, and is one under
+                // total internal reflection.
+                let reflectance =
+                    dielectric_reflectance(direction, geometric_normal, self.refractive_index);
+                let next_direction = match rng.next_f32() < reflectance {
+                    true => reflection_angle(direction, normal),
+                    false => refraction_angle(direction, geometric_normal, self.refractive_index)
+                        .map_or_else(|| reflection_angle(direction, normal), Vec3::normalize),
+                };
+                Some((next_direction, Vec3::splat(self.total), false))
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::{
+        AABB, Camera, Color, Emission, Emitters, PbrMaterial, Sphere, Traceable,
+    };
+    use crate::texture::{ColorInput, ScalarInput};
+    use image::{Rgb, Rgb32FImage};
+
+    /// Average radiance of paths hitting a unit sphere of `material` inside a uniformly white
+    /// environment.
+    fn furnace(material: Material) -> Vec3 {
+        let objects: Vec<Box<dyn Traceable>> = vec![Box::new(Sphere {
+            center: Vec3::new(0f32, 0f32, -3f32),
+            radius: 1f32,
+            material,
+        })];
+        let scene = Scene {
+            lights: Vec::new(),
+            objects,
+            background: Some(Rgb32FImage::from_pixel(1, 1, Rgb([1f32; 3]))),
+            camera: Camera::default(),
+            bvh: None,
+            emitters: Emitters::default(),
+        };
+
+        let mut rng = Rng::new(7);
+        let paths = 20000;
+        let mut sum = Vec3::ZERO;
+        for _ in 0..paths {
+            let direction = Vec3::new(
+                rng.next_f32() * 0.4 - 0.2,
+                rng.next_f32() * 0.4 - 0.2,
+                -1f32,
+            );
+            sum += trace_path(&scene, &Vec3::ZERO, &direction, &mut rng);
+        }
+        return sum / paths as f32;
+    }
+
+    #[test]
+    fn white_furnace_is_energy_conserving() {
+        // A perfectly white diffuse sphere reflects exactly as much light as it receives, so it
+        // must disappear into the background.
+        let mean = furnace(Material::Phong(PhongMaterial {
+            diffuse_color: ColorInput::Constant(Color::WHITE),
+            albedo: Vec4::new(1f32, 0f32, 0f32, 0f32),
+            specular_exponent: ScalarInput::Constant(1f32),
+            refractive_index: ScalarInput::Constant(1f32),
+            absorption: None,
+            normal_map: None,
+            bump_map: None,
+            emission: None,
+        }));
+
+        assert!((mean - Vec3::ONE).abs().max_element() < 0.02, "{mean}");
+    }
+
+    #[test]
+    fn pbr_materials_never_gain_energy() {
+        // Single-scattering microfacet models lose some energy on rough surfaces, but must
+        // never reflect more than they receive.
+        for (metallic, roughness, transmission) in
+            [(1f32, 0.5, 0f32), (0f32, 0.3, 0f32), (0f32, 0.2, 1f32)]
+        {
+            let mean = furnace(Material::Pbr(PbrMaterial {
+                base_color: ColorInput::Constant(Color::WHITE),
+                metallic: ScalarInput::Constant(metallic),
+                roughness: ScalarInput::Constant(roughness),
+                ior: ScalarInput::Constant(1.5),
+                transmission: ScalarInput::Constant(transmission),
+                absorption: None,
+                normal_map: None,
+                bump_map: None,
+                emission: None,
+            }));
+
+            assert!(
+                mean.max_element() < 1.02 && mean.min_element() > 0.8,
+                "{mean}"
+            );
+        }
+    }
+
+    #[test]
+    fn emissive_objects_are_counted_exactly_once() {
+        // Inside a closed box glowing with a radiance of one, a white sphere receives light
+        // from every direction and reflects a radiance of one as well. Missing either the
+        // explicit samples or the hits would lower it, and counting both would raise it.
+        let phong = |albedo: Vec4, emission: Option<Emission>| {
+            Material::Phong(PhongMaterial {
+                diffuse_color: ColorInput::Constant(Color::WHITE),
+                albedo,
+                specular_exponent: ScalarInput::Constant(20f32),
+                refractive_index: ScalarInput::Constant(1f32),
+                absorption: None,
+                normal_map: None,
+                bump_map: None,
+                emission,
+            })
+        };
+        let glow = Emission {
+            color: ColorInput::Constant(Color::WHITE),
+            strength: 1f32,
+        };
+        let rough_plastic = Material::Pbr(PbrMaterial {
+            base_color: ColorInput::Constant(Color::WHITE),
+            metallic: ScalarInput::Constant(0f32),
+            roughness: ScalarInput::Constant(0.4),
+            ior: ScalarInput::Constant(1.5),
+            transmission: ScalarInput::Constant(0f32),
+            absorption: None,
+            normal_map: None,
+            bump_map: None,
+            emission: None,
+        });
+
+        for material in [
+            phong(Vec4::new(1f32, 0f32, 0f32, 0f32), None),
+            phong(Vec4::new(0.5, 0f32, 0.5, 0f32), None),
+            rough_plastic,
+        ] {
+            let objects: Vec<Box<dyn Traceable>> = vec![
+                Box::new(AABB {
+                    min: Vec3::splat(-10f32),
+                    max: Vec3::splat(10f32),
+                    material: phong(Vec4::ZERO, Some(glow.clone())),
+                }),
+                Box::new(Sphere {
+                    center: Vec3::new(0f32, 0f32, -3f32),
+                    radius: 1f32,
+                    material,
+                }),
+            ];
+            let mut scene = Scene {
+                lights: Vec::new(),
+                objects,
+                background: None,
+                camera: Camera::default(),
+                bvh: None,
+                emitters: Emitters::default(),
+            };
+            scene.collect_emitters();
+
+            let mut rng = Rng::new(11);
+            let paths = 100000;
+            let mut sum = Vec3::ZERO;
+            for _ in 0..paths {
+                let direction = Vec3::new(
+                    rng.next_f32() * 0.4 - 0.2,
+                    rng.next_f32() * 0.4 - 0.2,
+                    -1f32,
+                );
+                sum += trace_path(&scene, &Vec3::ZERO, &direction, &mut rng);
+            }
+            let mean = sum / paths as f32;
+
+            assert!((mean - Vec3::ONE).abs().max_element() < 0.03, "{mean}");
+        }
+    }
+}
@@ -0,0 +1,229 @@
+#![allow(clippy::needless_return)]
+
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec3};
+
+use crate::sampling::cosine_hemisphere;
+use crate::structures::{Intersection, PbrMaterial};
+
+/// Smallest GGX alpha used, since a perfectly smooth distribution is a delta function that
+/// cannot be evaluated.
+const MIN_ALPHA: f32 = 1e-3;
+
+impl PbrMaterial {
+    /// Evaluates the material inputs at the shaded point.
+    pub fn surface(&self, intersection: &Intersection) -> PbrSurface {
+        return PbrSurface {
+            base_color: self.base_color.evaluate(intersection),
+            metallic: self.metallic.evaluate(intersection).clamp(0f32, 1f32),
+            roughness: self.roughness.evaluate(intersection).clamp(0f32, 1f32),
+            ior: self.ior.evaluate(intersection),
+            transmission: self.transmission.evaluate(intersection).clamp(0f32, 1f32),
+        };
+    }
+}
+
+/// A `PbrMaterial` at a single point, with its inputs evaluated and clamped to their ranges.
+pub struct PbrSurface {
+    /// Linear base color.
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub ior: f32,
+    pub transmission: f32,
+}
+
+impl PbrSurface {
+    /// GGX alpha, using the common `roughness²` remapping so that roughness feels linear.
+    pub fn alpha(&self) -> f32 {
+        return (self.roughness * self.roughness).max(MIN_ALPHA);
+    }
+
+    /// Reflectance at normal incidence. Dielectrics derive it from their IOR while metals take
+    /// the base color.
+    pub fn f0(&self) -> Vec3 {
+        let reflectance = (self.ior - 1f32) / (self.ior + 1f32);
+        let dielectric = Vec3::splat(reflectance * reflectance);
+        return dielectric.lerp(self.base_color, self.metallic);
+    }
+
+    /// Share of the light entering the surface that is scattered diffusely.
+    pub fn diffuse_weight(&self) -> f32 {
+        return (1f32 - self.metallic) * (1f32 - self.transmission);
+    }
+
+    /// Share of the light entering the surface that is transmitted through it.
+    pub fn transmission_weight(&self) -> f32 {
+        return (1f32 - self.metallic) * self.transmission;
+    }
+
+    /// Reflected part of the BSDF for light arriving from `light` and leaving toward `view`,
+    /// both pointing away from the surface. The diffuse term only receives the light that the
+    /// Fresnel term does not reflect, which keeps the sum from exceeding one.
+    pub fn evaluate(&self, view: &Vec3, normal: &Vec3, light: &Vec3) -> Vec3 {
+        let n_dot_v = normal.dot(*view);
+        let n_dot_l = normal.dot(*light);
+        if n_dot_v <= 0f32 || n_dot_l <= 0f32 {
+            return Vec3::ZERO;
+        }
+        let half = (*view + *light).normalize();
+        let alpha = self.alpha();
+        let fresnel = fresnel_schlick(self.f0(), view.dot(half));
+        let specular = fresnel
+            * ggx_distribution(normal.dot(half), alpha)
+            * smith_g1(n_dot_v, alpha)
+            * smith_g1(n_dot_l, alpha)
+            / (4f32 * n_dot_v * n_dot_l);
+        return self.evaluate_diffuse(view, normal, light) + specular;
+    }
+
+    /// Diffuse part of `evaluate`.
+    pub fn evaluate_diffuse(&self, view: &Vec3, normal: &Vec3, light: &Vec3) -> Vec3 {
+        if normal.dot(*view) <= 0f32 || normal.dot(*light) <= 0f32 {
+            return Vec3::ZERO;
+        }
+        let half = (*view + *light).normalize();
+        let fresnel = fresnel_schlick(self.f0(), view.dot(half));
+        return (Vec3::ONE - fresnel) * self.diffuse_weight() * self.base_color / PI;
+    }
+
+    /// Samples a scattered direction for a ray travelling along `direction`. `normal` faces the
+    /// side the ray arrives from and `entering` tells whether that side is the outside. Returns
+    /// the direction together with the BSDF times cosine over the sampling density and whether
+    /// the diffuse lobe was picked, or `None` when the path is absorbed. `u` picks the direction
+    /// and `choice` the lobe.
+    pub fn sample(
+        &self,
+        direction: &Vec3,
+        normal: &Vec3,
+        entering: bool,
+        u: Vec2,
+        choice: f32,
+    ) -> Option<(Vec3, Vec3, bool)> {
+        let view = -direction.normalize();
+        let base_color = self.base_color;
+        let diffuse_weight = self.diffuse_weight();
+        let diffuse_probability = 0.5 * diffuse_weight;
+
+        if choice < diffuse_probability {
+            let light = cosine_hemisphere(normal, u);
+            let half = (view + light).normalize();
+            let fresnel = fresnel_schlick(self.f0(), view.dot(half));
+            let weight = (Vec3::ONE - fresnel) * diffuse_weight * base_color / diffuse_probability;
+            return Some((light, weight, true));
+        }
+        let specular_probability = 1f32 - diffuse_probability;
+        let choice = (choice - diffuse_probability) / specular_probability;
+
+        let alpha = self.alpha();
+        let microfacet = sample_visible_normal(&view, normal, alpha, u);
+        let v_dot_m = view.dot(microfacet);
+        if v_dot_m <= 0f32 {
+            return None;
+        }
+        let transmission = self.transmission_weight();
+        let eta = match entering {
+            true => 1f32 / self.ior,
+            false => self.ior,
+        };
+        let refracted = match transmission > 0f32 {
+            true => (-view).refract(microfacet, eta),
+            false => Vec3::ZERO,
+        };
+        // Total internal reflection reflects everything.
+        let fresnel = match transmission > 0f32 && refracted == Vec3::ZERO {
+            true => Vec3::ONE,
+            false => fresnel_schlick(self.f0(), v_dot_m),
+        };
+        let reflect_probability = match refracted == Vec3::ZERO {
+            true => 1f32,
+            false => fresnel.element_sum() / 3f32,
+        };
+
+        // With visible normal sampling, the BSDF times cosine over the density of a reflected or
+        // refracted direction reduces to the Fresnel weight times the masking of that direction.
+        if choice < reflect_probability {
+            let light = microfacet * 2f32 * v_dot_m - view;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l <= 0f32 {
+                return None;
+            }
+            let weight =
+                fresnel * smith_g1(n_dot_l, alpha) / (reflect_probability * specular_probability);
+            return Some((light, weight, false));
+        }
+        let n_dot_l = -normal.dot(refracted);
+        if n_dot_l <= 0f32 {
+            return None;
+        }
+        let weight = (Vec3::ONE - fresnel) * transmission * base_color * smith_g1(n_dot_l, alpha)
+            / ((1f32 - reflect_probability) * specular_probability);
+        return Some((refracted, weight, false));
+    }
+}
+
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    return f0 + (Vec3::ONE - f0) * (1f32 - cos_theta.clamp(0f32, 1f32)).powi(5);
+}
+
+/// Exact Fresnel reflectance of unpolarized light at a smooth dielectric boundary. `cos_incident`
+/// is the cosine of the angle of incidence and `eta` the index of refraction of the incident side
+/// over that of the transmitted side. Returns one under total internal reflection.
+pub fn fresnel_dielectric(cos_incident: f32, eta: f32) -> f32 {
+    let cos_incident = cos_incident.clamp(0f32, 1f32);
+    let sin_transmitted2 = eta * eta * (1f32 - cos_incident * cos_incident);
+    if sin_transmitted2 >= 1f32 {
+        return 1f32;
+    }
+    let cos_transmitted = (1f32 - sin_transmitted2).sqrt();
+    let perpendicular =
+        (eta * cos_incident - cos_transmitted) / (eta * cos_incident + cos_transmitted);
+    let parallel = (cos_incident - eta * cos_transmitted) / (cos_incident + eta * cos_transmitted);
+    return 0.5 * (perpendicular * perpendicular + parallel * parallel);
+}
+
+/// GGX (Trowbridge-Reitz) normal distribution.
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha2 - 1f32) + 1f32;
+    return alpha2 / (PI * denominator * denominator);
+}
+
+/// Smith masking term for one direction, `cos_theta` being its cosine to the normal.
+fn smith_g1(cos_theta: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    return 2f32 * cos_theta
+        / (cos_theta + (alpha2 + (1f32 - alpha2) * cos_theta * cos_theta).sqrt());
+}
+
+/// Samples a microfacet normal from the GGX distribution of normals visible from `view`
+/// (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018).
+fn sample_visible_normal(view: &Vec3, normal: &Vec3, alpha: f32, u: Vec2) -> Vec3 {
+    let (tangent, bitangent) = normal.any_orthonormal_pair();
+    let local_view = Vec3::new(
+        view.dot(tangent) * alpha,
+        view.dot(bitangent) * alpha,
+        view.dot(*normal),
+    )
+    .normalize();
+
+    let length_squared = local_view.x * local_view.x + local_view.y * local_view.y;
+    let t1 = match length_squared > 0f32 {
+        true => Vec3::new(-local_view.y, local_view.x, 0f32) / length_squared.sqrt(),
+        false => Vec3::X,
+    };
+    let t2 = local_view.cross(t1);
+
+    let radius = u.x.sqrt();
+    let angle = 2f32 * PI * u.y;
+    let p1 = radius * angle.cos();
+    let s = 0.5 * (1f32 + local_view.z);
+    let p2 = (1f32 - s) * (1f32 - p1 * p1).max(0f32).sqrt() + s * radius * angle.sin();
+    let local_normal = t1 * p1 + t2 * p2 + local_view * (1f32 - p1 * p1 - p2 * p2).max(0f32).sqrt();
+
+    return (tangent * local_normal.x * alpha
+        + bitangent * local_normal.y * alpha
+        + *normal * local_normal.z.max(0f32))
+    .normalize();
+}
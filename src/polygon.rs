@@ -0,0 +1,98 @@
+#![allow(clippy::needless_return)]
+
+use glam::Vec3;
+
+/// Splits a planar polygon, convex or not, into triangles by ear clipping. Returns indices into
+/// `points`, wound the same way as the polygon. Polygons that are not simple, such as ones
+/// crossing themselves, are still covered but may get overlapping triangles.
+pub fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+    // Newell's method gives a normal that follows the winding even for concave polygons.
+    let normal = (0..points.len())
+        .map(|index| {
+            let (a, b) = (points[index], points[(index + 1) % points.len()]);
+            Vec3::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            )
+        })
+        .sum::<Vec3>();
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    while remaining.len() > 3 {
+        // Degenerate polygons may have no ear at all, in which case the first corner is cut off.
+        let ear = (0..remaining.len())
+            .find(|&corner| is_ear(points, &remaining, corner, normal))
+            .unwrap_or(0);
+        triangles.push(corner_triangle(&remaining, ear));
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    return triangles;
+}
+
+/// The corner at `corner` of `remaining` with its two neighbours.
+fn corner_triangle(remaining: &[usize], corner: usize) -> [usize; 3] {
+    let count = remaining.len();
+    return [
+        remaining[(corner + count - 1) % count],
+        remaining[corner],
+        remaining[(corner + 1) % count],
+    ];
+}
+
+/// Whether the triangle at `corner` can be cut off: it turns the same way as the polygon and no
+/// other corner lies inside of it.
+fn is_ear(points: &[Vec3], remaining: &[usize], corner: usize, normal: Vec3) -> bool {
+    let triangle = corner_triangle(remaining, corner);
+    let [a, b, c] = triangle.map(|index| points[index]);
+    if (b - a).cross(c - b).dot(normal) <= 0f32 {
+        return false;
+    }
+    let edge_side = |from: Vec3, to: Vec3, point: Vec3| (to - from).cross(point - from).dot(normal);
+    return remaining
+        .iter()
+        .filter(|index| !triangle.contains(index))
+        .all(|&index| {
+            let point = points[index];
+            // Corners on an edge count as outside, so that repeated positions do not block ears.
+            !(edge_side(a, b, point) > 0f32
+                && edge_side(b, c, point) > 0f32
+                && edge_side(c, a, point) > 0f32)
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concave_polygons_are_covered_exactly() {
+        // An L shape. A fan from its first corner would leave the polygon and flip a triangle.
+        let points = [
+            Vec3::new(2f32, 0f32, 0f32),
+            Vec3::new(2f32, 1f32, 0f32),
+            Vec3::new(1f32, 1f32, 0f32),
+            Vec3::new(1f32, 2f32, 0f32),
+            Vec3::new(0f32, 2f32, 0f32),
+            Vec3::new(0f32, 0f32, 0f32),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), points.len() - 2);
+        let mut area = 0f32;
+        for [a, b, c] in triangles {
+            let [a, b, c] = [a, b, c].map(|index| points[index]);
+            let twice_area = (b - a).cross(c - a).z;
+            assert!(
+                twice_area > 0f32,
+                "triangles keep the winding of the polygon"
+            );
+            area += twice_area / 2f32;
+        }
+        assert!((area - 3f32).abs() < 1e-5);
+    }
+}
@@ -1,19 +1,50 @@
+#![allow(clippy::needless_return)]
+
 use rayon::prelude::*;
 use std::{f32::consts::PI, mem::swap};
 
-use glam::Vec3;
-use image::{GenericImageView, ImageBuffer, Pixel, RgbImage};
+use clap::ValueEnum;
+use glam::{Vec2, Vec3};
+use image::{ImageBuffer, Rgb, Rgb32FImage};
+use serde::{Deserialize, Serialize};
+
+use crate::pathtracer::trace_path;
+use crate::pbr::{fresnel_dielectric, fresnel_schlick};
+use crate::sampling::Rng;
+use crate::structures::{
+    Color, Intersection, LightSample, Material, PbrMaterial, PhongMaterial, RenderSettings,
+    Resolution, Scene,
+};
+
+// TODO: Add variable for render distance?
+const RENDER_DISTANCE: f32 = 1000f32;
+
+/// Light transport algorithm used to shade camera rays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Integrator {
+    /// Whitted-style ray tracing: Phong lighting plus perfect reflection and refraction.
+    #[default]
+    Whitted,
+    /// Unbiased Monte Carlo path tracing, including indirect diffuse light.
+    Path,
+}
 
-use crate::structures::{Color, Intersection, Material, Scene};
+pub fn scene_intersect<'a>(
+    scene: &'a Scene,
+    origin: &Vec3,
+    direction: &Vec3,
+) -> Option<Intersection<'a>> {
+    if let Some(bvh) = &scene.bvh {
+        return bvh.intersect(&scene.objects, origin, direction, RENDER_DISTANCE);
+    }
 
-pub fn scene_intersect(scene: &Scene, origin: &Vec3, direction: &Vec3) -> Option<Intersection> {
     let mut closest_intersection: Option<Intersection> = None;
     let mut min_distance = f32::MAX;
     for object in &scene.objects {
         if let Some(intersection) = object.intersection(origin, direction) {
-            // TODO: Add variable for render distance?
             let intersection_distance = (intersection.point - origin).length();
-            if intersection_distance < min_distance && intersection_distance < 1000f32 {
+            if intersection_distance < min_distance && intersection_distance < RENDER_DISTANCE {
                 min_distance = intersection_distance;
                 closest_intersection = Some(intersection);
             }
@@ -22,36 +53,42 @@ pub fn scene_intersect(scene: &Scene, origin: &Vec3, direction: &Vec3) -> Option
     return closest_intersection;
 }
 
-fn cast_ray(scene: &Scene, origin: &Vec3, direction: &Vec3, recursive_depth: u8) -> Color {
-    let hit: Vec3;
-    let normal: Vec3;
-    let material: Material;
+/// Radiance arriving at `origin` from `direction`, through `medium` when the ray travels inside
+/// an object.
+fn cast_ray(
+    scene: &Scene,
+    origin: &Vec3,
+    direction: &Vec3,
+    medium: Option<&Material>,
+    recursive_depth: u8,
+    rng: &mut Rng,
+) -> Vec3 {
     let background_color = background_color(direction, scene);
 
     if recursive_depth > 4 {
         return background_color;
     }
 
-    match scene_intersect(scene, origin, direction) {
-        Some(intersection) => {
-            hit = intersection.point;
-            normal = intersection.normal;
-            material = intersection.material;
-        }
-        None => return background_color,
-    }
+    let Some(intersection) = scene_intersect(scene, origin, direction) else {
+        return background_color;
+    };
 
-    return color(
-        &scene,
-        &material,
-        &hit,
-        &normal,
-        &direction,
+    let calculated_color = color(
+        scene,
+        &intersection,
+        direction,
+        medium,
         recursive_depth,
+        rng,
     );
+    if let Some(medium) = medium {
+        let distance = (intersection.point - origin).length();
+        return calculated_color * medium.transmittance(distance);
+    }
+    return calculated_color;
 }
 
-fn background_color(direction: &Vec3, scene: &Scene) -> Color {
+pub fn background_color(direction: &Vec3, scene: &Scene) -> Vec3 {
     if let Some(background) = &scene.background {
         let norm_direction = direction.normalize();
 
@@ -74,91 +111,332 @@ fn background_color(direction: &Vec3, scene: &Scene) -> Color {
         let tex_x = u * (tex_width - 1) as f32;
         let tex_y = v * (tex_height - 1) as f32; // Try: (1.0 - v) * (tex_height - 1) as f32; if flipped
 
-        let pixel_color = background.get_pixel(tex_x as u32, tex_y as u32).to_rgb();
+        let pixel_color = background.get_pixel(tex_x as u32, tex_y as u32);
 
-        return Color {
-            r: pixel_color.0[0],
-            g: pixel_color.0[1],
-            b: pixel_color.0[2],
-        };
+        return Vec3::from_array(pixel_color.0);
     }
 
     return Color {
         r: 50,
         g: 180,
         b: 200,
-    };
+    }
+    .as_linear();
 }
 
 fn color(
     scene: &Scene,
-    material: &Material,
-    hit: &Vec3,
-    normal: &Vec3,
+    intersection: &Intersection,
     direction: &Vec3,
+    medium: Option<&Material>,
     recursive_depth: u8,
-) -> Color {
-    let reflection_color = reflection_color(scene, direction, normal, hit, recursive_depth);
-    let refraction_color =
-        refraction_color(scene, direction, normal, material, hit, recursive_depth);
+    rng: &mut Rng,
+) -> Vec3 {
+    let intersection = &Intersection {
+        normal: intersection.material.shading_normal(intersection),
+        ..*intersection
+    };
+    let reflected = match intersection.material {
+        Material::Phong(material) => phong_color(
+            scene,
+            material,
+            intersection,
+            direction,
+            medium,
+            recursive_depth,
+            rng,
+        ),
+        Material::Pbr(material) => pbr_color(
+            scene,
+            material,
+            intersection,
+            direction,
+            medium,
+            recursive_depth,
+            rng,
+        ),
+    };
+    return intersection.material.emitted_radiance(intersection) + reflected;
+}
 
-    let reflection_vector = reflection_color.as_vector();
-    let refraction_vector = refraction_color.as_vector();
+fn phong_color(
+    scene: &Scene,
+    material: &PhongMaterial,
+    intersection: &Intersection,
+    direction: &Vec3,
+    medium: Option<&Material>,
+    recursive_depth: u8,
+    rng: &mut Rng,
+) -> Vec3 {
+    let material = material.surface(intersection);
+    let hit = &intersection.point;
+    let normal = &intersection.normal;
+    // Dielectrics split their refraction weight between reflection and refraction with the
+    // Fresnel term, which sends all of it into the reflection under total internal reflection.
+    let fresnel = match material.albedo[3] > 0f32 {
+        true => dielectric_reflectance(direction, normal, material.refractive_index),
+        false => 0f32,
+    };
+    let reflection_weight = material.albedo[2] + material.albedo[3] * fresnel;
+    let refraction_weight = material.albedo[3] * (1f32 - fresnel);
+
+    let reflection_vector = match reflection_weight != 0f32 {
+        true => reflection_color(scene, direction, normal, hit, medium, recursive_depth, rng),
+        false => Vec3::ZERO,
+    };
+    let refraction_vector = match refraction_weight != 0f32 {
+        true => refraction_color(
+            scene,
+            direction,
+            intersection,
+            material.refractive_index,
+            medium,
+            recursive_depth,
+            rng,
+        ),
+        false => Vec3::ZERO,
+    };
 
-    let mut diffuse_light_intensity = 0f32;
-    let mut specular_light_intensity = 0f32;
-    let mut calculated_color = material.diffuse_color.as_vector();
+    let mut diffuse_light_intensity = Vec3::ZERO;
+    let mut specular_light_intensity = Vec3::ZERO;
+    let mut calculated_color = material.diffuse_color;
     for light in &scene.lights {
-        let light_direction = (light.position - hit).normalize();
-        let light_distance = (light.position - hit).length();
-        let shadow_origin = ray_offset(&light_direction, normal, hit);
-        if let Some(shadow_intersection) = scene_intersect(scene, &shadow_origin, &light_direction)
-        {
-            if (shadow_intersection.point - shadow_origin).length() < light_distance {
+        let sample_count = light.sample_count();
+        let light_tint = light.tint();
+        for sample_index in 0..sample_count {
+            let Some(light_sample) =
+                light.sample(hit, stratified_sample(sample_index, sample_count, rng))
+            else {
+                continue;
+            };
+            if is_shadowed(scene, hit, normal, &light_sample) {
                 continue;
             }
+            let light_direction = light_sample.direction;
+            diffuse_light_intensity += light_sample.radiance
+                * light_sample.attenuation
+                * f32::max(0f32, light_direction.dot(*normal))
+                / sample_count as f32;
+            specular_light_intensity += light_tint
+                * light_sample.attenuation
+                * f32::powf(
+                    f32::max(
+                        0f32,
+                        -reflection_angle(&(-light_direction), normal).dot(*direction),
+                    ),
+                    material.specular_exponent * light.intensity,
+                )
+                / sample_count as f32;
         }
-        diffuse_light_intensity += light.intensity * f32::max(0f32, light_direction.dot(*normal));
-        specular_light_intensity += f32::powf(
-            f32::max(
+    }
+
+    for light_sample in emitter_light(scene, hit, normal, rng) {
+        let light_direction = light_sample.direction;
+        let arriving = light_sample.radiance * light_sample.attenuation;
+        diffuse_light_intensity += arriving * f32::max(0f32, light_direction.dot(*normal));
+        specular_light_intensity += arriving
+            * f32::max(
                 0f32,
-                -reflection_angle(&(-light_direction), &normal).dot(*direction),
-            ),
-            material.specular_exponent * light.intensity,
-        )
+                -reflection_angle(&(-light_direction), normal).dot(*direction),
+            )
+            .powf(material.specular_exponent);
     }
 
     calculated_color = calculated_color * diffuse_light_intensity * material.albedo[0]
-        + Vec3::ONE * specular_light_intensity * material.albedo[1]
-        + reflection_vector * material.albedo[2]
-        + refraction_vector * material.albedo[3];
+        + specular_light_intensity * material.albedo[1]
+        + reflection_vector * reflection_weight
+        + refraction_vector * refraction_weight;
+
+    return calculated_color;
+}
+
+/// Shades a PBR material with direct light from the GGX BRDF. Indirect light is limited to a
+/// mirror reflection, faded out as the surface gets rougher, and a smooth refraction.
+fn pbr_color(
+    scene: &Scene,
+    material: &PbrMaterial,
+    intersection: &Intersection,
+    direction: &Vec3,
+    medium: Option<&Material>,
+    recursive_depth: u8,
+    rng: &mut Rng,
+) -> Vec3 {
+    let hit = &intersection.point;
+    let normal = &intersection.normal;
+    let surface = material.surface(intersection);
+    let view = -direction.normalize();
+    let facing_normal = match view.dot(*normal) < 0f32 {
+        true => -*normal,
+        false => *normal,
+    };
+
+    let mut calculated_color = Vec3::ZERO;
+    for light in &scene.lights {
+        let sample_count = light.sample_count();
+        for sample_index in 0..sample_count {
+            let Some(light_sample) =
+                light.sample(hit, stratified_sample(sample_index, sample_count, rng))
+            else {
+                continue;
+            };
+            if is_shadowed(scene, hit, &facing_normal, &light_sample) {
+                continue;
+            }
+            // Lights are calibrated so that a white Phong surface reflects their full
+            // intensity, which a white Lambertian BRDF only does when scaled by pi.
+            calculated_color += surface.evaluate(&view, &facing_normal, &light_sample.direction)
+                * light_sample.radiance
+                * light_sample.attenuation
+                * f32::max(0f32, light_sample.direction.dot(facing_normal))
+                * PI
+                / sample_count as f32;
+        }
+    }
+
+    for light_sample in emitter_light(scene, hit, &facing_normal, rng) {
+        calculated_color += surface.evaluate(&view, &facing_normal, &light_sample.direction)
+            * light_sample.radiance
+            * light_sample.attenuation
+            * f32::max(0f32, light_sample.direction.dot(facing_normal))
+            * PI;
+    }
+
+    let fresnel = fresnel_schlick(surface.f0(), view.dot(facing_normal));
+    let smoothness = (1f32 - surface.roughness).powi(2);
+    let mut reflection_weight = fresnel * smoothness;
+    let mut transmission = surface.transmission_weight();
+    // Past the critical angle the transmitted share is reflected back inside instead.
+    if transmission > 0f32 && refraction_angle(direction, normal, surface.ior).is_none() {
+        reflection_weight += (Vec3::ONE - fresnel) * transmission;
+        transmission = 0f32;
+    }
+    if reflection_weight != Vec3::ZERO {
+        calculated_color += reflection_weight
+            * reflection_color(scene, direction, normal, hit, medium, recursive_depth, rng);
+    }
+    if transmission > 0f32 {
+        calculated_color += (Vec3::ONE - fresnel)
+            * transmission
+            * surface.base_color
+            * refraction_color(
+                scene,
+                direction,
+                intersection,
+                surface.ior,
+                medium,
+                recursive_depth,
+                rng,
+            );
+    }
+
+    return calculated_color;
+}
+
+/// Unshadowed samples of the emissive objects seen from `hit`, each weighted by its share of the
+/// samples. Emitted radiance is divided by pi to match the calibration of lights: a white
+/// Lambertian surface reflects 1/pi of the radiance arriving from the hemisphere around it,
+/// whereas it reflects the full intensity of a light.
+fn emitter_light(scene: &Scene, hit: &Vec3, normal: &Vec3, rng: &mut Rng) -> Vec<LightSample> {
+    let sample_count = scene.emitters.sample_count();
+    let mut samples = Vec::new();
+    for sample_index in 0..sample_count {
+        let u = stratified_sample(sample_index, sample_count, rng);
+        let Some(mut light_sample) = scene.sample_emitters(hit, u, rng.next_f32()) else {
+            continue;
+        };
+        if is_shadowed(scene, hit, normal, &light_sample) {
+            continue;
+        }
+        light_sample.radiance /= PI * sample_count as f32;
+        samples.push(light_sample);
+    }
+    return samples;
+}
+
+/// Whether anything blocks the path from `hit` to the sampled point on a light.
+fn is_shadowed(scene: &Scene, hit: &Vec3, normal: &Vec3, light_sample: &LightSample) -> bool {
+    let shadow_origin = ray_offset(&light_sample.direction, normal, hit);
+    if let Some(shadow_intersection) =
+        scene_intersect(scene, &shadow_origin, &light_sample.direction)
+        && (shadow_intersection.point - shadow_origin).length() < light_sample.distance
+    {
+        return true;
+    }
+    return false;
+}
 
-    return Color::from_vector(calculated_color);
+/// Jittered position in the unit square for sample `index` of `count`, stratified so that
+/// area light samples cover the whole light instead of clumping. Counts that are not square get
+/// rows with one more column than the others, and every row is as tall as its share of the
+/// samples, so that each sample still has a cell of the same area.
+fn stratified_sample(index: u32, count: u32, rng: &mut Rng) -> Vec2 {
+    if count == 1 {
+        return Vec2::splat(0.5);
+    }
+    let rows = (count as f32).sqrt() as u32;
+    let columns = count / rows;
+    let wide_samples = (count % rows) * (columns + 1);
+    let (columns, column) = match index < wide_samples {
+        true => (columns + 1, index % (columns + 1)),
+        false => (columns, (index - wide_samples) % columns),
+    };
+    let row_start = (index - column) as f32 / count as f32;
+    let row_height = columns as f32 / count as f32;
+    return Vec2::new(
+        (column as f32 + rng.next_f32()) / columns as f32,
+        row_start + rng.next_f32() * row_height,
+    );
 }
 
+/// Light refracted through the surface at `intersection` from the `medium` the incident ray
+/// travels in.
 fn refraction_color(
     scene: &Scene,
     incident: &Vec3,
-    normal: &Vec3,
-    material: &Material,
-    hit: &Vec3,
+    intersection: &Intersection,
+    refractive_index: f32,
+    medium: Option<&Material>,
     recursive_depth: u8,
-) -> Color {
-    let refraction_direction = refraction_angle(incident, normal, material.refractive_index);
-    let refraction_origin = ray_offset(&refraction_direction, normal, hit);
+    rng: &mut Rng,
+) -> Vec3 {
+    let normal = &intersection.normal;
+    // No light gets through under total internal reflection. Callers account for it with the
+    // Fresnel term, so there is no need to trace a ray.
+    let Some(refraction_direction) = refraction_angle(incident, normal, refractive_index) else {
+        return Vec3::ZERO;
+    };
+    let refraction_origin = ray_offset(&refraction_direction, normal, &intersection.point);
     return cast_ray(
         scene,
         &refraction_origin,
         &refraction_direction,
+        crossed_medium(medium, intersection.material),
         recursive_depth + 1,
+        rng,
     );
 }
 
+/// Medium a ray travels in after refracting through a surface of `material` out of `medium`.
+/// Rays outside of any object enter the material and rays inside of one leave it, whichever way
+/// the surface happens to face, so that open meshes and flipped normals cannot make the air
+/// absorb light.
+pub fn crossed_medium<'a>(
+    medium: Option<&'a Material>,
+    material: &'a Material,
+) -> Option<&'a Material> {
+    return match medium {
+        Some(_) => None,
+        None => Some(material),
+    };
+}
+
+/// Origin for a ray leaving `hit` in `direction`, moved off the surface along `normal` to the
+/// side the ray travels to, so it cannot hit the same surface again from the side it left.
 fn ray_offset(direction: &Vec3, normal: &Vec3, hit: &Vec3) -> Vec3 {
     let offset = 0.0001f32;
     return match direction.dot(*normal) < 0f32 {
-        true => hit - offset,
-        false => hit + offset,
+        true => hit - normal * offset,
+        false => hit + normal * offset,
     };
 }
 
@@ -167,69 +445,385 @@ fn reflection_color(
     direction: &Vec3,
     normal: &Vec3,
     hit: &Vec3,
+    medium: Option<&Material>,
     recursive_depth: u8,
-) -> Color {
+    rng: &mut Rng,
+) -> Vec3 {
     let reflection_direction = reflection_angle(direction, normal);
-    let reflection_origin: Vec3;
-    if reflection_direction.dot(*normal) < 0f32 {
-        reflection_origin = hit - 0.0001f32;
-    } else {
-        reflection_origin = hit + 0.0001f32;
-    }
+    let reflection_origin = ray_offset(&reflection_direction, normal, hit);
     return cast_ray(
         scene,
         &reflection_origin,
         &reflection_direction,
+        medium,
         recursive_depth + 1,
+        rng,
     );
 }
 
-pub fn render(scene: Scene, output: &str) {
-    const IMAGE_WIDTH: u32 = 1024;
-    const IMAGE_HEIGHT: u32 = 768;
+pub fn render(scene: &Scene, settings: &RenderSettings) -> Rgb32FImage {
+    let Resolution { width, height } = settings.resolution;
+
+    let mut frame_buffer: Rgb32FImage = ImageBuffer::new(width, height);
+    let camera = &scene.camera;
 
-    let mut frame_buffer: RgbImage = ImageBuffer::new(IMAGE_WIDTH, IMAGE_HEIGHT);
-    let fov = PI / 2f32;
+    let sampling = &settings.sampling;
+    let filter_radius = sampling.filter_radius();
 
     frame_buffer
         .enumerate_pixels_mut()
         .par_bridge()
         .for_each(|(x, y, pixel)| {
-            let x_pos = (2f32 * (x as f32 + 0.5) / IMAGE_WIDTH as f32 - 1f32)
-                * f32::tan(fov / 2f32)
-                * IMAGE_WIDTH as f32
-                / IMAGE_HEIGHT as f32;
-            let y_pos =
-                -(2f32 * (y as f32 + 0.5) / IMAGE_HEIGHT as f32 - 1f32) * f32::tan(fov / 2f32);
-
-            let direction = Vec3::normalize(Vec3::new(x_pos, y_pos, -1f32));
-            *pixel = cast_ray(&scene, &Vec3::ZERO, &direction, 0).as_rgb();
+            let mut rng = Rng::for_pixel(sampling.seed, x, y);
+            let mut weighted_sum = Vec3::ZERO;
+            let mut weight_sum = 0f32;
+            let mut unweighted_sum = Vec3::ZERO;
+            let positions = sampling.pattern.generate(sampling.samples, &mut rng);
+            for position in &positions {
+                // Samples are spread over the whole filter footprint, which may reach into
+                // neighbouring pixels for the wider filters.
+                let offset = (*position - Vec2::splat(0.5)) * 2f32 * filter_radius;
+                let direction = camera.ray_direction(
+                    x as f32 + 0.5 + offset.x,
+                    y as f32 + 0.5 + offset.y,
+                    width,
+                    height,
+                );
+                let sample = match settings.integrator {
+                    Integrator::Whitted => {
+                        cast_ray(scene, &camera.position, &direction, None, 0, &mut rng)
+                    }
+                    Integrator::Path => trace_path(scene, &camera.position, &direction, &mut rng),
+                };
+                let weight = sampling.filter.weight(offset, filter_radius);
+                weighted_sum += sample * weight;
+                weight_sum += weight;
+                unweighted_sum += sample;
+            }
+
+            // Mitchell's negative lobes can cancel out the weights for very low sample counts.
+            let average = if weight_sum.abs() > f32::EPSILON {
+                weighted_sum / weight_sum
+            } else {
+                unweighted_sum / positions.len() as f32
+            };
+            *pixel = Rgb(average.to_array());
         });
 
-    frame_buffer.save(output).unwrap();
-    println!("Image has been rendered and saved to {output}!");
+    return frame_buffer;
 }
 
-fn reflection_angle(incident: &Vec3, normal: &Vec3) -> Vec3 {
+pub fn reflection_angle(incident: &Vec3, normal: &Vec3) -> Vec3 {
     return incident - normal * 2f32 * incident.dot(*normal);
 }
 
-fn refraction_angle(incident: &Vec3, normal: &Vec3, refractive_index: f32) -> Vec3 {
+/// Direction of a ray refracted through a boundary with `refractive_index` on the side `normal`
+/// points away from, or `None` under total internal reflection.
+pub fn refraction_angle(incident: &Vec3, normal: &Vec3, refractive_index: f32) -> Option<Vec3> {
+    let (cosi, eta, n) = refraction_frame(incident, normal, refractive_index);
+    let k = 1f32 - eta * eta * (1f32 - cosi * cosi);
+    if k < 0f32 {
+        return None;
+    }
+    return Some(incident * eta + n * (eta * cosi - f32::sqrt(k)));
+}
+
+/// Fraction of the light reflected rather than refracted at the same boundary as
+/// `refraction_angle`, which is one under total internal reflection.
+pub fn dielectric_reflectance(incident: &Vec3, normal: &Vec3, refractive_index: f32) -> f32 {
+    let (cosi, eta, _) = refraction_frame(incident, normal, refractive_index);
+    return fresnel_dielectric(cosi, eta);
+}
+
+/// Cosine of the angle of incidence, ratio of the indices of refraction and normal on the side
+/// the ray arrives from.
+#[allow(clippy::manual_clamp)]
+fn refraction_frame(incident: &Vec3, normal: &Vec3, refractive_index: f32) -> (f32, f32, Vec3) {
     let mut cosi = -f32::max(-1f32, f32::min(1f32, incident.dot(*normal)));
     let mut etai = 1f32;
     let mut etat = refractive_index;
-    let mut n = normal;
-    let normal_inverse = -normal;
-    let eta: f32;
+    let mut n = *normal;
     if cosi < 0f32 {
         cosi = -cosi;
         swap(&mut etai, &mut etat);
-        n = &normal_inverse;
+        n = -n;
     }
-    eta = etai / etat;
-    let k = 1f32 - eta * eta * (1f32 - cosi * cosi);
-    if k < 0f32 {
-        return Vec3::ZERO;
+    return (cosi, etai / etat, n);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathtracer::trace_path;
+    use crate::sampling::{PixelFilter, SamplePattern, SamplingSettings};
+    use crate::structures::{
+        AABB, Absorption, Camera, Emission, Emitters, Light, Sphere, Traceable, Triangle,
+    };
+    use crate::texture::{ColorInput, ScalarInput};
+    use glam::Vec4;
+    use image::{Rgb, Rgb32FImage};
+
+    fn test_scene() -> Scene {
+        // Small LCG so the scene is the same on every run without pulling in a rand crate.
+        let mut state = 0x2545f491u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1u32 << 24) as f32
+        };
+        let materials = [
+            Material::IVORY,
+            Material::GLASS,
+            Material::MIRROR,
+            Material::RED,
+        ];
+
+        let mut objects: Vec<Box<dyn Traceable>> = Vec::new();
+        for i in 0..200 {
+            let corner = Vec3::new(
+                next() * 8f32 - 4f32,
+                next() * 6f32 - 3f32,
+                -next() * 8f32 - 2f32,
+            );
+            let material = materials[i % materials.len()].clone();
+            match i % 3 {
+                0 => objects.push(Box::new(Sphere {
+                    center: corner,
+                    radius: next() * 0.4f32 + 0.05f32,
+                    material,
+                })),
+                1 => objects.push(Box::new(AABB {
+                    min: corner,
+                    max: corner + Vec3::new(next(), next(), next()) * 0.5f32,
+                    material,
+                })),
+                _ => objects.push(Box::new(Triangle {
+                    v0: corner,
+                    v1: corner + Vec3::new(next(), next(), next()) - 0.5f32,
+                    v2: corner + Vec3::new(next(), next(), next()) - 0.5f32,
+                    normals: None,
+                    uvs: None,
+                    material,
+                })),
+            }
+        }
+
+        Scene {
+            lights: vec![Light::point(Vec3::new(-3f32, 4f32, -1f32), 1.5)],
+            objects,
+            background: None,
+            camera: Camera::default(),
+            bvh: None,
+            emitters: Emitters::default(),
+        }
+    }
+
+    #[test]
+    fn bvh_matches_linear_traversal() {
+        let mut scene = test_scene();
+        let (width, height) = (64, 48);
+        let directions: Vec<Vec3> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let x_pos =
+                    (2f32 * (x as f32 + 0.5) / width as f32 - 1f32) * width as f32 / height as f32;
+                let y_pos = -(2f32 * (y as f32 + 0.5) / height as f32 - 1f32);
+                Vec3::new(x_pos, y_pos, -1f32).normalize()
+            })
+            .collect();
+
+        // Compared bit for bit, so that NaNs count as equal.
+        let trace = |scene: &Scene| -> Vec<[u32; 3]> {
+            directions
+                .iter()
+                .map(|direction| {
+                    cast_ray(scene, &Vec3::ZERO, direction, None, 0, &mut Rng::new(0))
+                        .to_array()
+                        .map(f32::to_bits)
+                })
+                .collect()
+        };
+        let linear = trace(&scene);
+        scene.build_bvh();
+        let accelerated = trace(&scene);
+
+        assert_eq!(linear, accelerated);
+    }
+
+    #[test]
+    fn fixed_seeds_render_the_same_image() {
+        let mut scene = test_scene();
+        scene.build_bvh();
+        let render_bits = |integrator: Integrator, seed: u64| -> Vec<u32> {
+            let settings = RenderSettings {
+                resolution: Resolution {
+                    width: 24,
+                    height: 18,
+                },
+                sampling: SamplingSettings {
+                    samples: 4,
+                    pattern: SamplePattern::Jittered,
+                    filter: PixelFilter::Gaussian,
+                    filter_radius: None,
+                    seed,
+                },
+                integrator,
+                ..Default::default()
+            };
+            // Pixels are rendered in parallel, in an order that changes from run to run.
+            let image = render(&scene, &settings);
+            return image.into_raw().into_iter().map(f32::to_bits).collect();
+        };
+        for integrator in [Integrator::Whitted, Integrator::Path] {
+            assert_eq!(render_bits(integrator, 7), render_bits(integrator, 7));
+            assert_ne!(render_bits(integrator, 7), render_bits(integrator, 8));
+        }
+    }
+
+    #[test]
+    fn stratified_samples_cover_the_square_for_any_count() {
+        let mut rng = Rng::new(3);
+        for count in 2..=12 {
+            // The extent of many samples of the same index approaches the cell of that index.
+            let cells: Vec<(Vec2, Vec2)> = (0..count)
+                .map(|index| {
+                    (0..500).fold((Vec2::ONE, Vec2::ZERO), |(min, max), _| {
+                        let sample = stratified_sample(index, count, &mut rng);
+                        (min.min(sample), max.max(sample))
+                    })
+                })
+                .collect();
+            let area: f32 = cells
+                .iter()
+                .map(|(min, max)| (*max - *min).element_product())
+                .sum();
+            assert!(area > 0.95 && area <= 1f32, "{count} samples cover {area}");
+            for (first, (min_a, max_a)) in cells.iter().enumerate() {
+                for (min_b, max_b) in &cells[first + 1..] {
+                    let overlap = (max_a.min(*max_b) - min_a.max(*min_b)).max(Vec2::ZERO);
+                    assert_eq!(overlap.element_product(), 0f32, "{count} samples overlap");
+                }
+            }
+        }
+    }
+
+    fn white_phong(albedo: Vec4) -> PhongMaterial {
+        return PhongMaterial {
+            diffuse_color: ColorInput::Constant(Color::WHITE),
+            albedo,
+            specular_exponent: ScalarInput::Constant(1f32),
+            refractive_index: ScalarInput::Constant(1f32),
+            absorption: None,
+            normal_map: None,
+            bump_map: None,
+            emission: None,
+        };
+    }
+
+    #[test]
+    fn emissive_objects_light_lambertian_surfaces() {
+        let (radius, height, strength) = (0.5f32, 5f32, 2f32);
+        let lamp = Sphere {
+            center: Vec3::new(0f32, height, 0f32),
+            radius,
+            material: Material::Phong(PhongMaterial {
+                emission: Some(Emission {
+                    color: ColorInput::Constant(Color::WHITE),
+                    strength,
+                }),
+                ..white_phong(Vec4::ZERO)
+            }),
+        };
+        let floor = AABB {
+            min: Vec3::new(-50f32, -1f32, -50f32),
+            max: Vec3::new(50f32, 0f32, 50f32),
+            material: Material::Phong(white_phong(Vec4::new(1f32, 0f32, 0f32, 0f32))),
+        };
+        let mut scene = Scene {
+            lights: Vec::new(),
+            objects: vec![Box::new(lamp), Box::new(floor)],
+            background: Some(Rgb32FImage::from_pixel(1, 1, Rgb([0f32; 3]))),
+            camera: Camera::default(),
+            bvh: None,
+            emitters: Emitters::default(),
+        };
+        scene.collect_emitters();
+
+        // A sphere straight above gives an irradiance of pi * L * (r / d)^2, and a white
+        // Lambertian surface reflects 1/pi of it.
+        let expected = strength * (radius / height).powi(2);
+        let renders = 400;
+        let mut total = Vec3::ZERO;
+        for seed in 0..renders {
+            let origin = Vec3::new(0f32, 1f32, 0f32);
+            total += cast_ray(&scene, &origin, &Vec3::NEG_Y, None, 0, &mut Rng::new(seed));
+        }
+        let average = total / renders as f32;
+        assert!(
+            average.abs_diff_eq(Vec3::splat(expected), expected * 0.03),
+            "{average}"
+        );
+    }
+
+    #[test]
+    fn absorption_only_applies_inside_objects() {
+        // Clear, with an index of refraction of one, so rays pass straight through unreflected.
+        let coefficient = Vec3::new(0.1, 0.5, 1f32);
+        let material = Material::Phong(PhongMaterial {
+            absorption: Some(Absorption::Coefficient(coefficient)),
+            ..white_phong(Vec4::new(0f32, 0f32, 0f32, 1f32))
+        });
+        let sphere = Sphere {
+            center: Vec3::new(0f32, 0f32, -3f32),
+            radius: 1f32,
+            material: material.clone(),
+        };
+        // A single triangle has no inside. This one faces away from the ray, which used to be
+        // taken for light leaving an object.
+        let facing_away = Triangle {
+            v0: Vec3::new(-1f32, -1f32, -2f32),
+            v1: Vec3::new(0f32, 1f32, -2f32),
+            v2: Vec3::new(1f32, -1f32, -2f32),
+            normals: None,
+            uvs: None,
+            material,
+        };
+        // Through the center, the sphere is crossed over its diameter.
+        let through_sphere = (-coefficient * 2f32).exp();
+        let cases: [(Box<dyn Traceable>, Vec3); 2] = [
+            (Box::new(sphere), through_sphere),
+            (Box::new(facing_away), Vec3::ONE),
+        ];
+        for (object, expected) in cases {
+            let scene = Scene {
+                lights: Vec::new(),
+                objects: vec![object],
+                background: Some(Rgb32FImage::from_pixel(1, 1, Rgb([1f32; 3]))),
+                camera: Camera::default(),
+                bvh: None,
+                emitters: Emitters::default(),
+            };
+            let whitted = cast_ray(&scene, &Vec3::ZERO, &Vec3::NEG_Z, None, 0, &mut Rng::new(0));
+            assert!(whitted.abs_diff_eq(expected, 1e-4), "{whitted}");
+            let path = trace_path(&scene, &Vec3::ZERO, &Vec3::NEG_Z, &mut Rng::new(0));
+            assert!(path.abs_diff_eq(expected, 1e-4), "{path}");
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel_and_total_internal_reflection() {
+        let normal = Vec3::Y;
+        // Head-on from the outside, glass reflects ((n - 1) / (n + 1))² = 4% of the light.
+        let reflectance = dielectric_reflectance(&Vec3::NEG_Y, &normal, 1.5);
+        assert!((reflectance - 0.04).abs() < 1e-4, "{reflectance}");
+        assert!(refraction_angle(&Vec3::NEG_Y, &normal, 1.5).is_some());
+
+        // At grazing angles nearly everything is reflected.
+        let grazing = Vec3::new(1f32, -0.01, 0f32).normalize();
+        assert!(dielectric_reflectance(&grazing, &normal, 1.5) > 0.9);
+
+        // From inside, beyond the critical angle of about 42 degrees, nothing gets out.
+        let inside = Vec3::new(1f32, 0.5, 0f32).normalize();
+        assert_eq!(refraction_angle(&inside, &normal, 1.5), None);
+        assert_eq!(dielectric_reflectance(&inside, &normal, 1.5), 1f32);
     }
-    return incident * eta + n * (eta * cosi - f32::sqrt(k));
 }
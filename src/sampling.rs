@@ -0,0 +1,250 @@
+#![allow(clippy::needless_return)]
+
+use clap::ValueEnum;
+use glam::{Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Small, fast, seedable generator (SplitMix64). Statistical quality is more than enough for
+/// picking sample positions, and keeping it in-tree makes renders reproducible across platforms.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Derives an independent generator for one pixel so that parallel rendering order does not
+    /// affect the result.
+    pub fn for_pixel(seed: u64, x: u32, y: u32) -> Rng {
+        let mut rng =
+            Rng::new(seed ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9e3779b97f4a7c15));
+        rng.next_u64();
+        return rng;
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        return z ^ (z >> 31);
+    }
+
+    /// Uniform float in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        return (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SamplePattern {
+    /// Evenly spaced samples. The sample count is rounded down to a square number.
+    Grid,
+    /// One random sample per grid cell. The sample count is rounded down to a square number.
+    Jittered,
+    /// Halton sequence in bases 2 and 3.
+    Halton,
+    /// First two dimensions of the Sobol sequence.
+    Sobol,
+}
+
+impl SamplePattern {
+    /// Returns sample positions in the unit square for a single pixel.
+    pub fn generate(&self, samples: u32, rng: &mut Rng) -> Vec<Vec2> {
+        let samples = samples.max(1);
+        match self {
+            SamplePattern::Grid | SamplePattern::Jittered => {
+                let cells = (samples as f32).sqrt().floor().max(1f32) as u32;
+                let mut positions = Vec::with_capacity((cells * cells) as usize);
+                for row in 0..cells {
+                    for column in 0..cells {
+                        let offset = match self {
+                            SamplePattern::Jittered => Vec2::new(rng.next_f32(), rng.next_f32()),
+                            _ => Vec2::splat(0.5),
+                        };
+                        positions
+                            .push((Vec2::new(column as f32, row as f32) + offset) / cells as f32);
+                    }
+                }
+                return positions;
+            }
+            SamplePattern::Halton | SamplePattern::Sobol => {
+                // A random toroidal shift per pixel (Cranley-Patterson rotation) keeps
+                // neighbouring pixels from sharing the exact same sample positions.
+                let shift = Vec2::new(rng.next_f32(), rng.next_f32());
+                return (0..samples)
+                    .map(|index| {
+                        let point = match self {
+                            SamplePattern::Halton => {
+                                Vec2::new(radical_inverse(index, 2), radical_inverse(index, 3))
+                            }
+                            _ => Vec2::new(radical_inverse(index, 2), sobol_second(index)),
+                        };
+                        (point + shift).fract()
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inverse_base = 1f32 / base as f32;
+    let mut fraction = inverse_base;
+    let mut result = 0f32;
+    while index > 0 {
+        result += (index % base) as f32 * fraction;
+        index /= base;
+        fraction *= inverse_base;
+    }
+    return result;
+}
+
+fn sobol_second(mut index: u32) -> f32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0u32;
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    return result as f32 / (1u64 << 32) as f32;
+}
+
+/// Direction in the hemisphere around `normal` with a density proportional to the cosine of its
+/// angle to `normal`, so that the cosine term of the rendering equation cancels out.
+pub fn cosine_hemisphere(normal: &Vec3, u: Vec2) -> Vec3 {
+    let radius = u.x.sqrt();
+    let angle = 2f32 * std::f32::consts::PI * u.y;
+    return local_direction(
+        normal,
+        radius * angle.cos(),
+        radius * angle.sin(),
+        (1f32 - u.x).sqrt(),
+    );
+}
+
+/// Direction spread uniformly over the unit sphere for `u` in the unit square.
+pub fn uniform_sphere(u: Vec2) -> Vec3 {
+    let z = 1f32 - 2f32 * u.x;
+    let radius = (1f32 - z * z).max(0f32).sqrt();
+    let angle = 2f32 * std::f32::consts::PI * u.y;
+    return Vec3::new(radius * angle.cos(), radius * angle.sin(), z);
+}
+
+/// Direction around `axis` distributed like the normalized Phong lobe `cos^exponent`.
+pub fn phong_lobe(axis: &Vec3, exponent: f32, u: Vec2) -> Vec3 {
+    let cos_theta = u.x.powf(1f32 / (exponent + 1f32));
+    let sin_theta = (1f32 - cos_theta * cos_theta).max(0f32).sqrt();
+    let angle = 2f32 * std::f32::consts::PI * u.y;
+    return local_direction(
+        axis,
+        sin_theta * angle.cos(),
+        sin_theta * angle.sin(),
+        cos_theta,
+    );
+}
+
+fn local_direction(axis: &Vec3, x: f32, y: f32, z: f32) -> Vec3 {
+    let (tangent, bitangent) = axis.any_orthonormal_pair();
+    return (tangent * x + bitangent * y + *axis * z).normalize();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFilter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl PixelFilter {
+    /// Half-width of the filter footprint in pixels when none is configured.
+    pub fn default_radius(&self) -> f32 {
+        return match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+            PixelFilter::Mitchell => 2.0,
+        };
+    }
+
+    /// Weight of a sample `offset` pixels away from the pixel center.
+    pub fn weight(&self, offset: Vec2, radius: f32) -> f32 {
+        return self.weight_1d(offset.x, radius) * self.weight_1d(offset.y, radius);
+    }
+
+    fn weight_1d(&self, x: f32, radius: f32) -> f32 {
+        let x = x.abs();
+        if x > radius {
+            return 0f32;
+        }
+        return match self {
+            PixelFilter::Box => 1f32,
+            PixelFilter::Tent => radius - x,
+            PixelFilter::Gaussian => {
+                const ALPHA: f32 = 2f32;
+                f32::max(
+                    0f32,
+                    (-ALPHA * x * x).exp() - (-ALPHA * radius * radius).exp(),
+                )
+            }
+            PixelFilter::Mitchell => {
+                // B = C = 1/3, as recommended by Mitchell and Netravali.
+                const B: f32 = 1f32 / 3f32;
+                const C: f32 = 1f32 / 3f32;
+                let x = 2f32 * x / radius;
+                if x < 1f32 {
+                    ((12f32 - 9f32 * B - 6f32 * C) * x * x * x
+                        + (-18f32 + 12f32 * B + 6f32 * C) * x * x
+                        + (6f32 - 2f32 * B))
+                        / 6f32
+                } else {
+                    ((-B - 6f32 * C) * x * x * x
+                        + (6f32 * B + 30f32 * C) * x * x
+                        + (-12f32 * B - 48f32 * C) * x
+                        + (8f32 * B + 24f32 * C))
+                        / 6f32
+                }
+            }
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SamplingSettings {
+    pub samples: u32,
+    pub pattern: SamplePattern,
+    pub filter: PixelFilter,
+    /// Half-width of the filter in pixels. Falls back to the filter's own default when unset.
+    pub filter_radius: Option<f32>,
+    pub seed: u64,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        SamplingSettings {
+            samples: 1,
+            pattern: SamplePattern::Grid,
+            filter: PixelFilter::Box,
+            filter_radius: None,
+            seed: 0,
+        }
+    }
+}
+
+impl SamplingSettings {
+    pub fn filter_radius(&self) -> f32 {
+        return self
+            .filter_radius
+            .unwrap_or_else(|| self.filter.default_radius());
+    }
+}
@@ -1,124 +1,1041 @@
-use glam::{Vec3, Vec4};
-use image::{DynamicImage, Rgb};
-use serde::{Deserialize, Serialize};
+#![allow(clippy::needless_return)]
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use glam::{BVec3, EulerRot, Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+use image::Rgb32FImage;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::bvh::Bvh;
+use crate::output::{ToneMapping, linear_to_srgb, srgb_to_linear};
+use crate::raytracer::Integrator;
+use crate::sampling::{SamplingSettings, uniform_sphere};
+use crate::texture::{BumpMap, ColorInput, ScalarInput, Texture, TextureUsage, apply_normal_map};
 
 pub const EPSILON: f32 = 1e-4;
+const EMITTER_SAMPLES: u32 = 16;
 
 pub struct Scene {
     pub lights: Vec<Light>,
     pub objects: Vec<Box<dyn Traceable>>,
-    pub background: Option<DynamicImage>,
+    /// Environment map holding linear radiance.
+    pub background: Option<Rgb32FImage>,
+    pub camera: Camera,
+    pub bvh: Option<Bvh>,
+    pub emitters: Emitters,
+}
+
+impl Scene {
+    /// Builds the acceleration structure over the current objects. Must be called again
+    /// whenever `objects` changes, otherwise intersections will use a stale hierarchy.
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(Bvh::build(&self.objects));
+    }
+
+    /// Finds the objects with an emissive material so that they can be sampled as lights. Like
+    /// `build_bvh`, must be called again whenever `objects` changes.
+    pub fn collect_emitters(&mut self) {
+        let mut emitters = Emitters::default();
+        let mut total = 0f32;
+        for (index, object) in self.objects.iter().enumerate() {
+            for (part, weight) in object.emitters() {
+                if weight > 0f32 {
+                    total += weight;
+                    emitters.parts.push((index, part));
+                    emitters.cumulative.push(total);
+                }
+            }
+        }
+        self.emitters = emitters;
+    }
+
+    /// Samples a point on one of the emissive objects as seen from `point`. `u` picks the point
+    /// on the object and `choice` the object. The attenuation of the sample turns its radiance
+    /// into the light arriving at `point`, divided by the density of the sample.
+    pub fn sample_emitters(&self, point: &Vec3, u: Vec2, choice: f32) -> Option<LightSample> {
+        let cumulative = &self.emitters.cumulative;
+        let total = *cumulative.last()?;
+        let index = cumulative
+            .partition_point(|&sum| sum <= choice * total)
+            .min(cumulative.len() - 1);
+        let previous = match index {
+            0 => 0f32,
+            _ => cumulative[index - 1],
+        };
+        let probability = (cumulative[index] - previous) / total;
+
+        let (object, part) = self.emitters.parts[index];
+        let (surface, area) = self.objects[object].sample_emitter(part, u);
+        let offset = surface.point - point;
+        let distance = offset.length();
+        if distance <= EPSILON {
+            return None;
+        }
+        let direction = offset / distance;
+        // Emissive surfaces glow from both sides.
+        let cos_light = surface.normal.dot(direction).abs();
+        return Some(LightSample {
+            direction,
+            // Stops short of the surface, so that it does not shadow itself.
+            distance: distance * (1f32 - 1e-3),
+            radiance: surface.material.emitted_radiance(&surface),
+            attenuation: cos_light * area / (distance * distance * probability),
+        });
+    }
+}
+
+/// Emissive parts of the objects of a scene, picked for light sampling in proportion to their area
+/// times their emission strength.
+#[derive(Debug, Default)]
+pub struct Emitters {
+    /// Indices into `Scene::objects`, each with the emissive part of the object.
+    parts: Vec<(usize, usize)>,
+    /// Running sum of the weights of the objects.
+    cumulative: Vec<f32>,
+}
+
+impl Emitters {
+    /// Number of samples the Whitted integrator spreads over the emissive objects.
+    pub fn sample_count(&self) -> u32 {
+        return match self.parts.is_empty() {
+            true => 0,
+            false => EMITTER_SAMPLES,
+        };
+    }
 }
 
 pub trait Traceable: Send + Sync {
-    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection>;
+    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection<'_>>;
+    fn bounding_box(&self) -> BoundingBox;
+    fn material(&self) -> &Material;
+    /// Surface area, used to sample emissive objects as lights.
+    fn area(&self) -> f32;
+    /// Point spread uniformly over the surface as `u` covers the unit square, with the geometric
+    /// normal of the surface.
+    fn sample_surface(&self, u: Vec2) -> Intersection<'_>;
+
+    /// Emissive parts of the object, each with the weight to pick it by for light sampling: its
+    /// area times its emission strength. Objects with a single material are a single part.
+    fn emitters(&self) -> Vec<(usize, f32)> {
+        return match self.material().emission() {
+            Some(emission) => vec![(0, self.area() * emission.strength.max(0f32))],
+            None => Vec::new(),
+        };
+    }
+
+    /// Point on the emissive `part` as `u` covers the unit square, with the area that its
+    /// probability density is the inverse of.
+    fn sample_emitter(&self, _part: usize, u: Vec2) -> (Intersection<'_>, f32) {
+        return (self.sample_surface(u), self.area());
+    }
+}
+
+/// Axis-aligned bounds used by the BVH. Unlike `AABB`, this is not renderable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox {
+    pub const EMPTY: BoundingBox = BoundingBox {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(a: Vec3, b: Vec3) -> BoundingBox {
+        BoundingBox {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(&self, point: Vec3) -> BoundingBox {
+        BoundingBox {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn padded(&self, amount: f32) -> BoundingBox {
+        BoundingBox {
+            min: self.min - Vec3::splat(amount),
+            max: self.max + Vec3::splat(amount),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        return (self.min + self.max) * 0.5;
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = self.max - self.min;
+        if extent.min_element() < 0f32 {
+            return 0f32;
+        }
+        return 2f32 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x);
+    }
+
+    /// Slab test returning the parametric entry distance of the ray, if it hits within
+    /// `[0, max_t]`. `inverse_direction` is expected to be `1.0 / direction` per component.
+    pub fn hit(&self, origin: &Vec3, inverse_direction: &Vec3, max_t: f32) -> Option<f32> {
+        let t1 = (self.min - origin) * inverse_direction;
+        let t2 = (self.max - origin) * inverse_direction;
+        // `min`/`max` on glam vectors drop NaNs produced by `0 * inf`, which keeps rays that
+        // lie exactly on a slab plane from being rejected.
+        let tmin = t1.min(t2).max_element().max(0f32);
+        let tmax = t1.max(t2).min_element().min(max_t);
+        if tmin > tmax {
+            return None;
+        }
+        return Some(tmin);
+    }
+}
+
+/// Options that control how a scene is rendered rather than what is in it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderSettings {
+    pub resolution: Resolution,
+    pub sampling: SamplingSettings,
+    pub tone_mapping: ToneMapping,
+    pub integrator: Integrator,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
-pub struct Light {
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution {
+            width: 1024,
+            height: 768,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Camera {
     pub position: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    /// Vertical field of view, in degrees.
+    pub fov: f32,
+    /// Width divided by height of the image plane. Follows the rendered image when unset.
+    pub aspect: Option<f32>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Vec3::ZERO,
+            target: Vec3::NEG_Z,
+            up: Vec3::Y,
+            fov: 90f32,
+            aspect: None,
+        }
+    }
+}
+
+impl Camera {
+    /// Checks that the camera describes an image plane, which it does not when it looks at its
+    /// own position or `up` is parallel to the view direction.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.position.is_finite() && self.target.is_finite() && self.up.is_finite()) {
+            return Err(String::from("position, target and up must be finite"));
+        }
+        let forward = self.target - self.position;
+        if forward.length() <= EPSILON {
+            return Err(String::from("target must differ from position"));
+        }
+        let right = forward.normalize().cross(self.up.normalize_or_zero());
+        if right.length() <= EPSILON {
+            return Err(String::from(
+                "up must not be zero or parallel to the view direction",
+            ));
+        }
+        if !(self.fov > 0f32 && self.fov < 180f32) {
+            return Err(String::from("fov must be between 0 and 180 degrees"));
+        }
+        if let Some(aspect) = self.aspect
+            && !(aspect > 0f32 && aspect.is_finite())
+        {
+            return Err(String::from("aspect must be a positive number"));
+        }
+        return Ok(());
+    }
+
+    /// Returns the normalized direction of the ray through the point `(x, y)` of a `width` by
+    /// `height` image, measured in pixels from the top left corner.
+    pub fn ray_direction(&self, x: f32, y: f32, width: u32, height: u32) -> Vec3 {
+        let forward = (self.target - self.position).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+
+        let half_height = f32::tan(self.fov.to_radians() / 2f32);
+        let screen_x = (2f32 * x / width as f32 - 1f32) * half_height;
+        let x_pos = match self.aspect {
+            Some(aspect) => screen_x * aspect,
+            None => screen_x * width as f32 / height as f32,
+        };
+        let y_pos = -(2f32 * y / height as f32 - 1f32) * half_height;
+
+        return (right * x_pos + up * y_pos + forward).normalize();
+    }
+}
+
+/// Placement of an object in the scene, applied as scale, then rotation, then translation, and
+/// finally `matrix` when there is one.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vec3,
+    /// Euler angles in degrees, applied around X, then Y, then Z.
+    pub rotation: Vec3,
+    /// Either a single uniform factor or one factor per axis.
+    #[serde(deserialize_with = "deserialize_scale")]
+    pub scale: Vec3,
+    /// Arbitrary affine transform, given as four rows.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix: Option<[[f32; 4]; 4]>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+            matrix: None,
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        let rotation = Quat::from_euler(
+            EulerRot::ZYX,
+            self.rotation.z.to_radians(),
+            self.rotation.y.to_radians(),
+            self.rotation.x.to_radians(),
+        );
+        let placement =
+            Mat4::from_scale_rotation_translation(self.scale, rotation, self.translation);
+        return match self.matrix {
+            // glam stores columns, while scene files list rows.
+            Some(rows) => Mat4::from_cols_array_2d(&rows).transpose() * placement,
+            None => placement,
+        };
+    }
+}
+
+/// Checks that `matrix` places objects without projecting them and can be inverted, as rays are
+/// moved into the space of the objects and normals need the inverse transpose.
+pub fn validate_matrix(matrix: &Mat4) -> Result<(), String> {
+    if !matrix.is_finite() {
+        return Err(String::from("must be finite"));
+    }
+    if matrix.row(3) != Vec4::W {
+        return Err(String::from(
+            "must not project, its last row has to be 0, 0, 0, 1",
+        ));
+    }
+    let linear = Mat3::from_mat4(*matrix);
+    if linear.determinant() == 0f32 || !linear.inverse().is_finite() {
+        return Err(String::from(
+            "must be invertible, which a scale of zero along any axis is not",
+        ));
+    }
+    return Ok(());
+}
+
+fn deserialize_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec3, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a number or a sequence of 3 f32 values")]
+    enum Scale {
+        Uniform(f32),
+        PerAxis(Vec3),
+    }
+    return match Scale::deserialize(deserializer)? {
+        Scale::Uniform(scale) => Ok(Vec3::splat(scale)),
+        Scale::PerAxis(scale) => Ok(scale),
+    };
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Light {
+    #[serde(flatten)]
+    pub kind: LightKind,
     pub intensity: f32,
+    #[serde(default = "Color::white")]
+    pub color: Color,
+    /// Blackbody temperature in Kelvin, which tints `color` when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub attenuation: Attenuation,
+}
+
+/// How a light fades with distance, tagged by `"model"` in the scene JSON. Has no effect on
+/// directional lights.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum Attenuation {
+    /// Same intensity at any distance.
+    #[default]
+    None,
+    /// Falls off with `1 / distance`.
+    Linear,
+    /// Physically based `1 / distance²` falloff.
+    InverseSquare,
+    /// Inverse-square falloff windowed to reach exactly zero at `range`, so that a light only
+    /// affects its surroundings. Well within the range it matches `InverseSquare`.
+    Smooth { range: f32 },
+}
+
+impl Attenuation {
+    /// Checks that a smooth falloff has a distance to fall off over.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Attenuation::Smooth { range } = self
+            && !(*range > 0f32 && range.is_finite())
+        {
+            return Err(String::from("range must be positive"));
+        }
+        return Ok(());
+    }
+
+    pub fn factor(&self, distance: f32) -> f32 {
+        // Keeps lights from blowing up when a surface touches them.
+        let distance = distance.max(EPSILON);
+        return match self {
+            Attenuation::None => 1f32,
+            Attenuation::Linear => 1f32 / distance,
+            Attenuation::InverseSquare => 1f32 / (distance * distance),
+            Attenuation::Smooth { range } => {
+                let ratio = distance / range;
+                let window = (1f32 - ratio * ratio * ratio * ratio).clamp(0f32, 1f32);
+                window * window / (distance * distance)
+            }
+        };
+    }
 }
 
+/// Shape of a light, tagged by `"type"` in the scene JSON.
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
-pub struct Material {
-    pub diffuse_color: Color,
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LightKind {
+    Point {
+        position: Vec3,
+    },
+    /// Infinitely distant light, such as the sun, shining along `direction`.
+    Directional {
+        direction: Vec3,
+    },
+    /// Point light limited to a cone around `direction`. `cone_angle` is the half-angle of the
+    /// cone in degrees and `falloff` the width in degrees of the soft edge inside it.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        cone_angle: f32,
+        #[serde(default)]
+        falloff: f32,
+    },
+    /// Parallelogram centered on `position` and spanned by `edge_u` and `edge_v`. Only the side
+    /// that `edge_u × edge_v` points to emits, and less so toward grazing angles.
+    Rectangle {
+        position: Vec3,
+        edge_u: Vec3,
+        edge_v: Vec3,
+        #[serde(default = "default_area_light_samples")]
+        samples: u32,
+    },
+    Sphere {
+        position: Vec3,
+        radius: f32,
+        #[serde(default = "default_area_light_samples")]
+        samples: u32,
+    },
+}
+
+fn default_area_light_samples() -> u32 {
+    return 16;
+}
+
+/// A single point on a light as seen from a shaded point.
+pub struct LightSample {
+    /// Unit vector from the shaded point toward the light.
+    pub direction: Vec3,
+    /// Distance to the sampled point, infinite for directional lights.
+    pub distance: f32,
+    /// Color times intensity of the light.
+    pub radiance: Vec3,
+    /// Fraction of `radiance` that reaches the shaded point, e.g. outside of a spot cone.
+    pub attenuation: f32,
+}
+
+impl Light {
+    pub fn point(position: Vec3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Point { position },
+            intensity,
+            color: Color::WHITE,
+            temperature: None,
+            attenuation: Attenuation::None,
+        }
+    }
+
+    /// Per-channel color of the light, excluding its intensity.
+    pub fn tint(&self) -> Vec3 {
+        let color = self.color.as_linear();
+        return match self.temperature {
+            Some(temperature) => color * blackbody_color(temperature),
+            None => color,
+        };
+    }
+
+    /// Number of shadow rays needed to resolve the light. Area lights spread their intensity
+    /// over all of their samples.
+    pub fn sample_count(&self) -> u32 {
+        return match self.kind {
+            LightKind::Rectangle { samples, .. } | LightKind::Sphere { samples, .. } => {
+                samples.max(1)
+            }
+            _ => 1,
+        };
+    }
+
+    /// Samples the light as seen from `point`, where `u` is a position in the unit square used
+    /// to pick a point on area lights. Returns `None` if the light cannot reach `point`.
+    pub fn sample(&self, point: &Vec3, u: Vec2) -> Option<LightSample> {
+        let toward = |position: Vec3| {
+            let offset = position - point;
+            (offset.normalize(), offset.length())
+        };
+        let (direction, distance, attenuation) = match self.kind {
+            LightKind::Point { position } => {
+                let (direction, distance) = toward(position);
+                (direction, distance, 1f32)
+            }
+            LightKind::Directional { direction } => (-direction.normalize(), f32::INFINITY, 1f32),
+            LightKind::Spot {
+                position,
+                direction: spot_direction,
+                cone_angle,
+                falloff,
+            } => {
+                let (direction, distance) = toward(position);
+                let outer = cone_angle.to_radians().cos();
+                let inner = (cone_angle - falloff).max(0f32).to_radians().cos();
+                let cos_angle = (-direction).dot(spot_direction.normalize());
+                let factor = if inner - outer > EPSILON {
+                    let t = ((cos_angle - outer) / (inner - outer)).clamp(0f32, 1f32);
+                    t * t * (3f32 - 2f32 * t)
+                } else if cos_angle >= outer {
+                    1f32
+                } else {
+                    0f32
+                };
+                if factor <= 0f32 {
+                    return None;
+                }
+                (direction, distance, factor)
+            }
+            LightKind::Rectangle {
+                position,
+                edge_u,
+                edge_v,
+                ..
+            } => {
+                let sample_point = position + (u.x - 0.5) * edge_u + (u.y - 0.5) * edge_v;
+                let (direction, distance) = toward(sample_point);
+                let facing = (-direction).dot(edge_u.cross(edge_v).normalize_or_zero());
+                if facing <= 0f32 {
+                    return None;
+                }
+                (direction, distance, facing)
+            }
+            LightKind::Sphere {
+                position, radius, ..
+            } => {
+                // Sampling the disk that faces `point` covers the visible part of the sphere.
+                let (axis, _) = toward(position);
+                let (tangent, bitangent) = axis.any_orthonormal_pair();
+                let radial = radius * u.x.sqrt();
+                let angle = 2f32 * PI * u.y;
+                let sample_point =
+                    position + radial * (angle.cos() * tangent + angle.sin() * bitangent);
+                let (direction, distance) = toward(sample_point);
+                (direction, distance, 1f32)
+            }
+        };
+        let attenuation = match distance.is_finite() {
+            true => attenuation * self.attenuation.factor(distance),
+            false => attenuation,
+        };
+        return Some(LightSample {
+            direction,
+            distance,
+            radiance: self.tint() * self.intensity,
+            attenuation,
+        });
+    }
+}
+
+/// Approximate linear color of a blackbody radiator, normalized so that the brightest channel is
+/// one. Uses Tanner Helland's curve fit, which is accurate enough between 1000K and 40000K and
+/// gives sRGB encoded values.
+pub fn blackbody_color(temperature: f32) -> Vec3 {
+    let temperature = temperature.clamp(1000f32, 40000f32) / 100f32;
+    let red = if temperature <= 66f32 {
+        255f32
+    } else {
+        329.69873 * (temperature - 60f32).powf(-0.13320476)
+    };
+    let green = if temperature <= 66f32 {
+        99.4708 * temperature.ln() - 161.11957
+    } else {
+        288.12216 * (temperature - 60f32).powf(-0.07551485)
+    };
+    let blue = if temperature >= 66f32 {
+        255f32
+    } else if temperature <= 19f32 {
+        0f32
+    } else {
+        138.51773 * (temperature - 10f32).ln() - 305.0448
+    };
+    let encoded = (Vec3::new(red, green, blue) / 255f32).clamp(Vec3::ZERO, Vec3::ONE);
+    return encoded.map(srgb_to_linear);
+}
+
+/// Surface description, tagged by `"model"` in the scene JSON.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "model", rename_all = "lowercase")]
+pub enum Material {
+    Phong(PhongMaterial),
+    Pbr(PbrMaterial),
+}
+
+/// Ad-hoc Phong material. `albedo` weighs the diffuse, specular, reflected and refracted terms.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PhongMaterial {
+    pub diffuse_color: ColorInput,
+    pub albedo: Vec4,
+    pub specular_exponent: ScalarInput,
+    pub refractive_index: ScalarInput,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absorption: Option<Absorption>,
+    /// Tangent-space normal map, with red, green and blue holding x, y and z remapped to `[0, 1]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<Texture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<BumpMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Emission>,
+}
+
+/// A `PhongMaterial` at a single point, with its inputs evaluated.
+pub struct PhongSurface {
+    /// Linear diffuse color.
+    pub diffuse_color: Vec3,
     pub albedo: Vec4,
     pub specular_exponent: f32,
     pub refractive_index: f32,
 }
 
-#[allow(dead_code)]
+impl PhongMaterial {
+    /// Evaluates the material inputs at the shaded point.
+    pub fn surface(&self, intersection: &Intersection) -> PhongSurface {
+        return PhongSurface {
+            diffuse_color: self.diffuse_color.evaluate(intersection),
+            albedo: self.albedo,
+            specular_exponent: self.specular_exponent.evaluate(intersection),
+            refractive_index: self.refractive_index.evaluate(intersection),
+        };
+    }
+}
+
+/// Metal/roughness material using a GGX microfacet BRDF.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PbrMaterial {
+    pub base_color: ColorInput,
+    /// Blends from a dielectric (0) to a metal (1), whose reflections are tinted by `base_color`.
+    #[serde(default = "default_metallic")]
+    pub metallic: ScalarInput,
+    /// Perceptual roughness from mirror-like (0) to fully rough (1).
+    #[serde(default = "default_roughness")]
+    pub roughness: ScalarInput,
+    /// Index of refraction, which sets the reflectance of dielectrics and bends transmitted light.
+    #[serde(default = "default_ior")]
+    pub ior: ScalarInput,
+    /// Fraction of the non-metallic part that is transmitted instead of diffusely reflected.
+    #[serde(default = "default_transmission")]
+    pub transmission: ScalarInput,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absorption: Option<Absorption>,
+    /// Tangent-space normal map, with red, green and blue holding x, y and z remapped to `[0, 1]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<Texture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<BumpMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Emission>,
+}
+
+/// Beer-Lambert absorption of light travelling through the inside of an object, which tints
+/// thick glass more than thin glass.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Absorption {
+    /// Absorption coefficient per unit of distance for each channel.
+    Coefficient(Vec3),
+    /// Color that white light takes on after travelling `distance` inside the object.
+    AtDistance { color: Color, distance: f32 },
+}
+
+impl Absorption {
+    pub fn coefficient(&self) -> Vec3 {
+        return match self {
+            Absorption::Coefficient(coefficient) => coefficient.max(Vec3::ZERO),
+            Absorption::AtDistance { color, distance } => {
+                // A channel that is fully black would need an infinite coefficient.
+                -color.as_linear().max(Vec3::splat(EPSILON)).map(f32::ln) / distance.max(EPSILON)
+            }
+        };
+    }
+}
+
+/// Light given off by a surface, the same from both of its sides.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Emission {
+    #[serde(default = "default_emission_color")]
+    pub color: ColorInput,
+    /// Multiplies `color`, which allows surfaces brighter than white.
+    #[serde(default = "default_emission_strength")]
+    pub strength: f32,
+}
+
+impl Emission {
+    pub fn radiance(&self, intersection: &Intersection) -> Vec3 {
+        return self.color.evaluate(intersection) * self.strength;
+    }
+}
+
+fn default_emission_color() -> ColorInput {
+    return ColorInput::Constant(Color::WHITE);
+}
+
+fn default_emission_strength() -> f32 {
+    return 1f32;
+}
+
+fn default_metallic() -> ScalarInput {
+    return ScalarInput::Constant(0f32);
+}
+
+fn default_roughness() -> ScalarInput {
+    return ScalarInput::Constant(0.5);
+}
+
+fn default_ior() -> ScalarInput {
+    return ScalarInput::Constant(1.5);
+}
+
+fn default_transmission() -> ScalarInput {
+    return ScalarInput::Constant(0f32);
+}
+
+impl Material {
+    /// Textures used by the material, along with the name of the slot they are in and how that
+    /// slot reads them.
+    pub fn textures_mut(&mut self) -> Vec<(&'static str, TextureUsage, &mut Texture)> {
+        let (mut slots, normal_map, bump_map, emission) = match self {
+            Material::Phong(material) => (
+                vec![
+                    (
+                        "diffuse_color",
+                        TextureUsage::Color,
+                        material.diffuse_color.texture_mut(),
+                    ),
+                    (
+                        "specular_exponent",
+                        TextureUsage::Data,
+                        material.specular_exponent.texture_mut(),
+                    ),
+                    (
+                        "refractive_index",
+                        TextureUsage::Data,
+                        material.refractive_index.texture_mut(),
+                    ),
+                ],
+                &mut material.normal_map,
+                &mut material.bump_map,
+                &mut material.emission,
+            ),
+            Material::Pbr(material) => (
+                vec![
+                    (
+                        "base_color",
+                        TextureUsage::Color,
+                        material.base_color.texture_mut(),
+                    ),
+                    (
+                        "metallic",
+                        TextureUsage::Data,
+                        material.metallic.texture_mut(),
+                    ),
+                    (
+                        "roughness",
+                        TextureUsage::Data,
+                        material.roughness.texture_mut(),
+                    ),
+                    ("ior", TextureUsage::Data, material.ior.texture_mut()),
+                    (
+                        "transmission",
+                        TextureUsage::Data,
+                        material.transmission.texture_mut(),
+                    ),
+                ],
+                &mut material.normal_map,
+                &mut material.bump_map,
+                &mut material.emission,
+            ),
+        };
+        slots.push(("normal_map", TextureUsage::Data, normal_map.as_mut()));
+        slots.push((
+            "bump_map.texture",
+            TextureUsage::Data,
+            bump_map.as_mut().map(|bump_map| &mut bump_map.texture),
+        ));
+        slots.push((
+            "emission.color",
+            TextureUsage::Color,
+            emission
+                .as_mut()
+                .and_then(|emission| emission.color.texture_mut()),
+        ));
+        return slots
+            .into_iter()
+            .filter_map(|(slot, usage, texture)| Some((slot, usage, texture?)))
+            .collect();
+    }
+
+    pub fn emission(&self) -> Option<&Emission> {
+        return match self {
+            Material::Phong(material) => material.emission.as_ref(),
+            Material::Pbr(material) => material.emission.as_ref(),
+        };
+    }
+
+    /// Radiance given off by the surface at the intersection.
+    pub fn emitted_radiance(&self, intersection: &Intersection) -> Vec3 {
+        return match self.emission() {
+            Some(emission) => emission.radiance(intersection),
+            None => Vec3::ZERO,
+        };
+    }
+
+    /// Normal used for shading at the intersection, bent by the normal map and then the bump map
+    /// of the material. Perturbations that would tip it below the surface are ignored.
+    pub fn shading_normal(&self, intersection: &Intersection) -> Vec3 {
+        let (normal_map, bump_map) = match self {
+            Material::Phong(material) => (&material.normal_map, &material.bump_map),
+            Material::Pbr(material) => (&material.normal_map, &material.bump_map),
+        };
+        let mut normal = intersection.normal;
+        if let Some(normal_map) = normal_map {
+            normal = apply_normal_map(normal_map, intersection, &normal);
+        }
+        if let Some(bump_map) = bump_map {
+            normal = bump_map.apply(intersection, &normal);
+        }
+        return match normal.dot(intersection.normal) > 0f32 {
+            true => normal,
+            false => intersection.normal,
+        };
+    }
+
+    /// Fraction of each channel left after light travels `distance` inside the object.
+    pub fn transmittance(&self, distance: f32) -> Vec3 {
+        let absorption = match self {
+            Material::Phong(material) => material.absorption,
+            Material::Pbr(material) => material.absorption,
+        };
+        return match absorption {
+            Some(absorption) => (-absorption.coefficient() * distance).exp(),
+            None => Vec3::ONE,
+        };
+    }
+}
+
 impl Material {
-    pub const IVORY: Material = Material {
-        diffuse_color: Color {
+    /// Materials that scene files can refer to by name without defining them.
+    pub const PREDEFINED: [(&'static str, Material); 8] = [
+        ("ivory", Material::IVORY),
+        ("green", Material::GREEN),
+        ("red", Material::RED),
+        ("brown", Material::BROWN),
+        ("purple", Material::PURPLE),
+        ("orange", Material::ORANGE),
+        ("mirror", Material::MIRROR),
+        ("glass", Material::GLASS),
+    ];
+
+    pub const IVORY: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 202,
             g: 202,
             b: 176,
-        },
+        }),
         albedo: Vec4::new(0.6, 0.3, 0.05, 0.0),
-        specular_exponent: 50f32,
-        refractive_index: 1f32,
-    };
-    pub const GREEN: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(50f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const GREEN: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 36,
             g: 105,
             b: 25,
-        },
+        }),
         albedo: Vec4::new(0.9, 0.1, 0.0, 0.0),
-        specular_exponent: 10f32,
-        refractive_index: 1f32,
-    };
-    pub const RED: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(10f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const RED: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 36,
             g: 25,
             b: 25,
-        },
+        }),
         albedo: Vec4::new(0.9, 0.1, 0.0, 0.0),
-        specular_exponent: 10f32,
-        refractive_index: 1f32,
-    };
-    pub const BROWN: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(10f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const BROWN: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 123,
             g: 63,
             b: 0,
-        },
+        }),
         albedo: Vec4::new(0.9, 0.1, 0.0, 0.0),
-        specular_exponent: 40f32,
-        refractive_index: 1f32,
-    };
-    pub const PURPLE: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(40f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const PURPLE: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 75,
             g: 0,
             b: 130,
-        },
+        }),
         albedo: Vec4::new(0.9, 0.1, 0.0, 0.0),
-        specular_exponent: 40f32,
-        refractive_index: 1f32,
-    };
-    pub const ORANGE: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(40f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const ORANGE: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 250,
             g: 69,
             b: 1,
-        },
+        }),
         albedo: Vec4::new(0.9, 0.1, 0.0, 0.0),
-        specular_exponent: 40f32,
-        refractive_index: 1f32,
-    };
-    pub const MIRROR: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(40f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const MIRROR: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 255,
             g: 255,
             b: 255,
-        },
+        }),
         albedo: Vec4::new(0.0, 10.0, 0.8, 0.0),
-        specular_exponent: 1425f32,
-        refractive_index: 1f32,
-    };
-    pub const GLASS: Material = Material {
-        diffuse_color: Color {
+        specular_exponent: ScalarInput::Constant(1425f32),
+        refractive_index: ScalarInput::Constant(1f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
+    pub const GLASS: Material = Material::Phong(PhongMaterial {
+        diffuse_color: ColorInput::Constant(Color {
             r: 150,
             g: 175,
             b: 200,
-        },
+        }),
         albedo: Vec4::new(0.0, 0.5, 0.1, 0.8),
-        specular_exponent: 125f32,
-        refractive_index: 1.5f32,
-    };
+        specular_exponent: ScalarInput::Constant(125f32),
+        refractive_index: ScalarInput::Constant(1.5f32),
+        absorption: None,
+        normal_map: None,
+        bump_map: None,
+        emission: None,
+    });
 }
 
-pub struct Intersection {
+#[derive(Clone, Copy)]
+pub struct Intersection<'a> {
     pub point: Vec3,
     pub normal: Vec3,
-    pub material: Material,
+    /// Texture coordinates of the point.
+    pub uv: Vec2,
+    /// Direction in which `u` grows along the surface. `w` is the handedness of the frame: the
+    /// direction in which `v` grows is `w * normal.cross(tangent)`.
+    pub tangent: Vec4,
+    pub material: &'a Material,
+}
+
+impl Intersection<'_> {
+    /// Orthonormal tangent and bitangent around `normal`, following the growth of `u` and `v`.
+    pub fn tangent_frame(&self, normal: &Vec3) -> (Vec3, Vec3) {
+        let tangent = self.tangent.truncate();
+        let tangent = (tangent - *normal * normal.dot(tangent))
+            .try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        return (tangent, self.tangent.w * normal.cross(tangent));
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -126,13 +1043,14 @@ pub struct Sphere {
 }
 
 impl Traceable for Sphere {
-    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection> {
+    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection<'_>> {
         let l = self.center - origin;
         let tca = l.dot(*direction);
         if tca < 0f32 {
             return None;
         }
-        let d = f32::sqrt(l.dot(l) - tca * tca);
+        // Rounding can take the squared distance below zero for rays through the center.
+        let d = f32::sqrt((l.dot(l) - tca * tca).max(0f32));
         if d < 0f32 || d > self.radius {
             return None;
         }
@@ -145,13 +1063,45 @@ impl Traceable for Sphere {
         if t0 < 0f32 {
             return None;
         }
-        let intersection_point = origin + direction * t0;
-        let intersection_normal = (intersection_point - self.center).normalize();
-        return Some(Intersection {
-            point: intersection_point,
-            normal: intersection_normal,
-            material: self.material,
-        });
+        return Some(self.surface_point(origin + direction * t0));
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        return BoundingBox {
+            min: self.center - Vec3::splat(self.radius),
+            max: self.center + Vec3::splat(self.radius),
+        };
+    }
+
+    fn material(&self) -> &Material {
+        return &self.material;
+    }
+
+    fn area(&self) -> f32 {
+        return 4f32 * PI * self.radius * self.radius;
+    }
+
+    fn sample_surface(&self, u: Vec2) -> Intersection<'_> {
+        return self.surface_point(self.center + uniform_sphere(u) * self.radius);
+    }
+}
+
+impl Sphere {
+    fn surface_point(&self, point: Vec3) -> Intersection<'_> {
+        let normal = (point - self.center).normalize();
+        // Longitude and latitude, with the seam facing -z and v running from the bottom pole.
+        let uv = Vec2::new(
+            0.5 + f32::atan2(normal.x, normal.z) / (2f32 * PI),
+            0.5 + normal.y.clamp(-1f32, 1f32).asin() / PI,
+        );
+        let tangent = Vec3::new(normal.z, 0f32, -normal.x);
+        return Intersection {
+            point,
+            normal,
+            uv,
+            tangent: tangent.extend(1f32),
+            material: &self.material,
+        };
     }
 }
 
@@ -163,13 +1113,16 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn from_vector(vector: Vec3) -> Color {
-        Color {
-            r: (f32::clamp(vector.x, 0f32, 1f32) * 255f32) as u8,
-            g: (f32::clamp(vector.y, 0f32, 1f32) * 255f32) as u8,
-            b: (f32::clamp(vector.z, 0f32, 1f32) * 255f32) as u8,
-        }
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    pub fn white() -> Color {
+        return Color::WHITE;
     }
+
     pub fn as_vector(&self) -> Vec3 {
         return Vec3::new(
             self.r as f32 / 255f32,
@@ -178,8 +1131,19 @@ impl Color {
         );
     }
 
-    pub fn as_rgb(&self) -> Rgb<u8> {
-        return Rgb([self.r, self.g, self.b]);
+    /// Decodes the sRGB encoded color into linear values for shading.
+    pub fn as_linear(&self) -> Vec3 {
+        return self.as_vector().map(srgb_to_linear);
+    }
+
+    /// Encodes linear values into the nearest sRGB color, clamping them to `[0, 1]`.
+    pub fn from_linear(linear: Vec3) -> Color {
+        let encode = |value: f32| (linear_to_srgb(value.clamp(0f32, 1f32)) * 255f32).round() as u8;
+        return Color {
+            r: encode(linear.x),
+            g: encode(linear.y),
+            b: encode(linear.z),
+        };
     }
 }
 
@@ -187,11 +1151,16 @@ pub struct Triangle {
     pub v0: Vec3,
     pub v1: Vec3,
     pub v2: Vec3,
+    /// Per-vertex shading normals. The flat face normal is used when these are absent.
+    pub normals: Option<[Vec3; 3]>,
+    /// Per-vertex texture coordinates. Triangles without them map to the origin of the texture.
+    pub uvs: Option<[Vec2; 3]>,
     pub material: Material,
 }
 
 impl Traceable for Triangle {
-    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection> {
+    #[allow(clippy::manual_range_contains)]
+    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection<'_>> {
         let v0v1 = self.v1 - self.v0;
         let v0v2 = self.v2 - self.v0;
         let pvec = direction.cross(v0v2);
@@ -218,14 +1187,81 @@ impl Traceable for Triangle {
             return None;
         }
 
-        Some(Intersection {
-            point: origin + t * direction,
-            normal: v0v1.cross(v0v2).normalize(),
-            material: self.material,
-        })
+        Some(self.surface_point(origin + t * direction, u, v))
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        return BoundingBox::from_points(self.v0, self.v1).grow(self.v2);
+    }
+
+    fn material(&self) -> &Material {
+        return &self.material;
+    }
+
+    fn area(&self) -> f32 {
+        return 0.5 * (self.v1 - self.v0).cross(self.v2 - self.v0).length();
+    }
+
+    fn sample_surface(&self, u: Vec2) -> Intersection<'_> {
+        let root = u.x.sqrt();
+        let (b1, b2) = (root * (1f32 - u.y), root * u.y);
+        let point = (1f32 - b1 - b2) * self.v0 + b1 * self.v1 + b2 * self.v2;
+        return Intersection {
+            normal: (self.v1 - self.v0).cross(self.v2 - self.v0).normalize(),
+            ..self.surface_point(point, b1, b2)
+        };
+    }
+}
+
+impl Triangle {
+    /// Point with barycentric weights `u` and `v` for the second and third vertex.
+    fn surface_point(&self, point: Vec3, u: f32, v: f32) -> Intersection<'_> {
+        let v0v1 = self.v1 - self.v0;
+        let v0v2 = self.v2 - self.v0;
+        let normal = match self.normals {
+            Some([n0, n1, n2]) => ((1f32 - u - v) * n0 + u * n1 + v * n2).normalize(),
+            None => v0v1.cross(v0v2).normalize(),
+        };
+
+        let uv = match self.uvs {
+            Some([uv0, uv1, uv2]) => (1f32 - u - v) * uv0 + u * uv1 + v * uv2,
+            None => Vec2::ZERO,
+        };
+
+        return Intersection {
+            point,
+            normal,
+            uv,
+            tangent: self.tangent(&normal),
+            material: &self.material,
+        };
+    }
+
+    /// Tangent derived from how the texture coordinates change across the triangle. Triangles
+    /// without usable texture coordinates get an arbitrary one.
+    fn tangent(&self, normal: &Vec3) -> Vec4 {
+        let fallback = normal.any_orthonormal_vector().extend(1f32);
+        let Some([uv0, uv1, uv2]) = self.uvs else {
+            return fallback;
+        };
+        let (edge1, edge2) = (self.v1 - self.v0, self.v2 - self.v0);
+        let (delta1, delta2) = (uv1 - uv0, uv2 - uv0);
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() < f32::EPSILON {
+            return fallback;
+        }
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) / determinant;
+        let bitangent = (edge2 * delta1.x - edge1 * delta2.x) / determinant;
+        // Mirrored texture coordinates flip the bitangent relative to the normal.
+        let handedness = match normal.cross(tangent).dot(bitangent) < 0f32 {
+            true => -1f32,
+            false => 1f32,
+        };
+        return tangent.extend(handedness);
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct AABB {
     pub min: Vec3,
     pub max: Vec3,
@@ -233,7 +1269,7 @@ pub struct AABB {
 }
 
 impl Traceable for AABB {
-    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection> {
+    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection<'_>> {
         let inv_dir = Vec3::new(
             if direction.x == 0.0 {
                 f32::INFINITY
@@ -279,8 +1315,49 @@ impl Traceable for AABB {
             overall_tmin
         };
 
-        let hit_point = *origin + *direction * t_hit;
+        Some(self.surface_point(*origin + *direction * t_hit))
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        return BoundingBox::from_points(self.min, self.max);
+    }
+
+    fn material(&self) -> &Material {
+        return &self.material;
+    }
+
+    fn area(&self) -> f32 {
+        let size = self.max - self.min;
+        return 2f32 * (size.y * size.z + size.x * size.z + size.x * size.y);
+    }
+
+    fn sample_surface(&self, u: Vec2) -> Intersection<'_> {
+        let size = self.max - self.min;
+        // Areas of the faces perpendicular to each axis. `u.x` first picks a pair of faces by
+        // area, and is then stretched back over the unit interval to pick a side and a point.
+        let areas = [size.y * size.z, size.x * size.z, size.x * size.y];
+        let mut choice = u.x * areas.iter().sum::<f32>();
+        let mut axis = 0;
+        while axis < 2 && choice >= areas[axis] {
+            choice -= areas[axis];
+            axis += 1;
+        }
+        let t = (choice / areas[axis].max(f32::MIN_POSITIVE) * 2f32).clamp(0f32, 2f32);
+        let side = match t < 1f32 {
+            true => self.min[axis],
+            false => self.max[axis],
+        };
+        let (first, second) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut point = Vec3::ZERO;
+        point[axis] = side;
+        point[first] = self.min[first] + size[first] * t.fract();
+        point[second] = self.min[second] + size[second] * u.y;
+        return self.surface_point(point);
+    }
+}
 
+impl AABB {
+    fn surface_point(&self, hit_point: Vec3) -> Intersection<'_> {
         let center = (self.min + self.max) * 0.5;
         let point_relative_to_center = hit_point - center;
         let half_extents = (self.max - self.min) * 0.5;
@@ -297,10 +1374,497 @@ impl Traceable for AABB {
             normal = Vec3::new(0.0, 0.0, point_relative_to_center.z.signum());
         }
 
-        Some(Intersection {
+        // Every face shows the whole texture, spanned by the two axes the face extends along.
+        let face_position = (hit_point - self.min) / (self.max - self.min);
+        let (uv, tangent) = if normal.x != 0.0 {
+            (
+                Vec2::new(face_position.z, face_position.y),
+                Vec4::new(0.0, 0.0, 1.0, -normal.x),
+            )
+        } else if normal.y != 0.0 {
+            (
+                Vec2::new(face_position.x, face_position.z),
+                Vec4::new(1.0, 0.0, 0.0, -normal.y),
+            )
+        } else {
+            (
+                Vec2::new(face_position.x, face_position.y),
+                Vec4::new(1.0, 0.0, 0.0, normal.z),
+            )
+        };
+
+        Intersection {
             point: hit_point,
             normal,
-            material: self.material,
-        })
+            uv,
+            tangent,
+            material: &self.material,
+        }
+    }
+}
+
+/// Objects that can be placed many times over by `Instance`s. They keep their own hierarchy, in
+/// the space they were modelled in.
+pub struct Mesh {
+    objects: Vec<Box<dyn Traceable>>,
+    bvh: Bvh,
+    bounds: BoundingBox,
+}
+
+impl Mesh {
+    /// Returns `None` when there are no objects, as there would be nothing to place.
+    pub fn new(objects: Vec<Box<dyn Traceable>>) -> Option<Mesh> {
+        if objects.is_empty() {
+            return None;
+        }
+        let bounds = objects.iter().fold(BoundingBox::EMPTY, |bounds, object| {
+            bounds.union(&object.bounding_box())
+        });
+        return Some(Mesh {
+            bvh: Bvh::build(&objects),
+            objects,
+            bounds,
+        });
+    }
+}
+
+/// A shared `Mesh` placed in the scene by a transform. Rays are moved into the space of the mesh
+/// instead of the mesh into the scene, so any number of instances store its objects only once.
+pub struct Instance {
+    mesh: Arc<Mesh>,
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    /// Inverse transpose of the linear part of `object_to_world`, which keeps normals
+    /// perpendicular under non-uniform scaling.
+    normal_matrix: Mat3,
+    /// -1 when the transform mirrors the mesh, which flips the handedness of its tangent frames.
+    handedness: f32,
+}
+
+impl Instance {
+    /// Fails when the transform cannot be undone, which rays and normals rely on.
+    pub fn new(mesh: Arc<Mesh>, transform: &Transform) -> Result<Instance, String> {
+        return Instance::from_matrix(mesh, transform.matrix());
+    }
+
+    pub fn from_matrix(mesh: Arc<Mesh>, object_to_world: Mat4) -> Result<Instance, String> {
+        validate_matrix(&object_to_world)?;
+        let linear = Mat3::from_mat4(object_to_world);
+        return Ok(Instance {
+            mesh,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+            normal_matrix: linear.inverse().transpose(),
+            handedness: match linear.determinant() < 0f32 {
+                true => -1f32,
+                false => 1f32,
+            },
+        });
+    }
+
+    fn to_world_space<'a>(&self, intersection: Intersection<'a>) -> Intersection<'a> {
+        let tangent = self
+            .object_to_world
+            .transform_vector3(intersection.tangent.truncate());
+        return Intersection {
+            point: self.object_to_world.transform_point3(intersection.point),
+            normal: (self.normal_matrix * intersection.normal).normalize(),
+            tangent: tangent.extend(intersection.tangent.w * self.handedness),
+            ..intersection
+        };
+    }
+
+    /// Factor by which the transform stretches the area of the mesh around a point with the unit
+    /// geometric `normal`: the determinant of the linear part times the length of the transformed
+    /// normal.
+    fn area_scale(&self, normal: Vec3) -> f32 {
+        return (self.normal_matrix * normal).length() / self.normal_matrix.determinant().abs();
+    }
+
+    /// Area of each object in world space. Exact for flat objects such as triangles, while
+    /// curved ones are measured by the stretch at their middle.
+    fn world_areas(&self) -> impl Iterator<Item = f32> + '_ {
+        return self.mesh.objects.iter().map(|object| {
+            let middle = object.sample_surface(Vec2::splat(0.5));
+            object.area() * self.area_scale(middle.normal)
+        });
+    }
+}
+
+impl Traceable for Instance {
+    fn intersection(&self, origin: &Vec3, direction: &Vec3) -> Option<Intersection<'_>> {
+        let local_origin = self.world_to_object.transform_point3(*origin);
+        // Spheres expect a unit direction. Hits are still ordered correctly, as a transform
+        // keeps the order of points along a line.
+        let local_direction = self
+            .world_to_object
+            .transform_vector3(*direction)
+            .normalize();
+        let intersection = self.mesh.bvh.intersect(
+            &self.mesh.objects,
+            &local_origin,
+            &local_direction,
+            f32::INFINITY,
+        )?;
+        return Some(self.to_world_space(intersection));
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        let bounds = self.mesh.bounds;
+        return (0..8).fold(BoundingBox::EMPTY, |world_bounds, corner: u32| {
+            let mask = BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0);
+            let point = Vec3::select(mask, bounds.max, bounds.min);
+            world_bounds.grow(self.object_to_world.transform_point3(point))
+        });
+    }
+
+    /// The material of the first object. Emissive objects are found by `emitters`, which looks
+    /// at the material of each object.
+    fn material(&self) -> &Material {
+        return self.mesh.objects[0].material();
+    }
+
+    fn area(&self) -> f32 {
+        return self.world_areas().sum();
+    }
+
+    fn sample_surface(&self, u: Vec2) -> Intersection<'_> {
+        // As in `AABB::sample_surface`, `u.x` picks an object by area and is then stretched back
+        // over the unit interval to pick a point on it.
+        let mut total = 0f32;
+        let cumulative: Vec<f32> = self
+            .world_areas()
+            .map(|area| {
+                total += area;
+                total
+            })
+            .collect();
+        let choice = u.x * total;
+        let index = cumulative
+            .partition_point(|&sum| sum <= choice)
+            .min(cumulative.len() - 1);
+        let previous = match index {
+            0 => 0f32,
+            _ => cumulative[index - 1],
+        };
+        let stretched = ((choice - previous)
+            / (cumulative[index] - previous).max(f32::MIN_POSITIVE))
+        .clamp(0f32, 1f32);
+        let surface = self.mesh.objects[index].sample_surface(Vec2::new(stretched, u.y));
+        return self.to_world_space(surface);
+    }
+
+    /// Every emissive object of the mesh is a part of its own, so that meshes can mix materials.
+    fn emitters(&self) -> Vec<(usize, f32)> {
+        return self
+            .mesh
+            .objects
+            .iter()
+            .zip(self.world_areas())
+            .enumerate()
+            .filter_map(|(index, (object, area))| {
+                let emission = object.material().emission()?;
+                Some((index, area * emission.strength.max(0f32)))
+            })
+            .collect();
+    }
+
+    /// The density of a point on an object is measured by the stretch at that very point, which
+    /// keeps it exact for curved objects under non-uniform scaling.
+    fn sample_emitter(&self, part: usize, u: Vec2) -> (Intersection<'_>, f32) {
+        let object = &self.mesh.objects[part];
+        let surface = object.sample_surface(u);
+        let area = object.area() * self.area_scale(surface.normal);
+        return (self.to_world_space(surface), area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attenuation_models_fall_off_as_documented() {
+        for distance in [0.5, 1f32, 2f32, 10f32] {
+            assert_eq!(Attenuation::None.factor(distance), 1f32);
+            assert_eq!(Attenuation::Linear.factor(distance), 1f32 / distance);
+            assert_eq!(
+                Attenuation::InverseSquare.factor(distance),
+                1f32 / (distance * distance)
+            );
+        }
+        let smooth = Attenuation::Smooth { range: 100f32 };
+        for distance in [0.5, 1f32, 2f32, 10f32] {
+            let ratio = smooth.factor(distance) * distance * distance;
+            assert!(ratio > 0.99 && ratio <= 1f32, "{distance}: {ratio}");
+        }
+        let mut previous = f32::INFINITY;
+        for distance in [20f32, 50f32, 90f32, 99f32] {
+            let factor = smooth.factor(distance);
+            assert!(factor > 0f32 && factor < previous);
+            previous = factor;
+        }
+        assert_eq!(smooth.factor(100f32), 0f32);
+        assert_eq!(smooth.factor(150f32), 0f32);
+
+        assert!(smooth.validate().is_ok());
+        assert!(Attenuation::Smooth { range: 0f32 }.validate().is_err());
+        assert!(Attenuation::Smooth { range: -1f32 }.validate().is_err());
+    }
+
+    #[test]
+    fn rectangle_lights_emit_from_their_front_side() {
+        let light = Light {
+            kind: LightKind::Rectangle {
+                position: Vec3::ZERO,
+                edge_u: Vec3::X,
+                edge_v: Vec3::Y,
+                samples: 1,
+            },
+            ..Light::point(Vec3::ZERO, 1f32)
+        };
+        let center = Vec2::splat(0.5);
+        let facing = |point: Vec3| {
+            light
+                .sample(&point, center)
+                .map(|sample| sample.attenuation)
+        };
+
+        assert_eq!(facing(Vec3::new(0f32, 0f32, 2f32)), Some(1f32));
+        let oblique = facing(Vec3::new(2f32, 0f32, 2f32)).unwrap();
+        assert!((oblique - 0.5f32.sqrt()).abs() < 1e-6, "{oblique}");
+        assert_eq!(facing(Vec3::new(2f32, 0f32, 0f32)), None);
+        assert_eq!(facing(Vec3::new(0f32, 0f32, -2f32)), None);
+    }
+
+    #[test]
+    fn blackbody_colors_are_linear() {
+        // Tanner Helland's fit gives about (255, 167, 87) at 2700K, which is encoded in sRGB.
+        let color = blackbody_color(2700f32);
+        assert!(
+            color.abs_diff_eq(Vec3::new(1f32, 0.385, 0.096), 2e-3),
+            "{color}"
+        );
+    }
+
+    #[test]
+    fn blackbody_tints_stay_within_the_fitted_range() {
+        assert_eq!(blackbody_color(500f32), blackbody_color(1000f32));
+        assert_eq!(blackbody_color(60000f32), blackbody_color(40000f32));
+        // Candlelight has barely any blue, and the sky of 40000K is blue-white.
+        let (warm, cold) = (blackbody_color(1000f32), blackbody_color(40000f32));
+        assert_eq!(warm.x, 1f32);
+        assert_eq!(warm.z, 0f32);
+        assert_eq!(cold.z, 1f32);
+        assert!(cold.x < cold.y && cold.y < 1f32, "{cold}");
+        assert!(blackbody_color(6600f32).abs_diff_eq(Vec3::ONE, 1e-3));
+
+        let mut light = Light::point(Vec3::ZERO, 1f32);
+        light.color = Color {
+            r: 255,
+            g: 128,
+            b: 255,
+        };
+        assert_eq!(light.tint(), light.color.as_linear());
+        light.temperature = Some(2700f32);
+        assert_eq!(
+            light.tint(),
+            light.color.as_linear() * blackbody_color(2700f32)
+        );
+    }
+
+    #[test]
+    fn default_camera_keeps_the_original_projection() {
+        // Scenes without a camera block used to be rendered with this projection.
+        let (width, height) = (1024, 768);
+        let half_height = f32::tan(PI / 4f32);
+        let camera = Camera::default();
+        for (x, y) in [(0, 0), (511, 383), (1023, 767), (100, 700)] {
+            let x_pos =
+                (2f32 * (x as f32 + 0.5) / width as f32 - 1f32) * half_height * width as f32
+                    / height as f32;
+            let y_pos = -(2f32 * (y as f32 + 0.5) / height as f32 - 1f32) * half_height;
+            let expected = Vec3::new(x_pos, y_pos, -1f32).normalize();
+            let actual = camera.ray_direction(x as f32 + 0.5, y as f32 + 0.5, width, height);
+            assert!(actual.abs_diff_eq(expected, 1e-6));
+        }
+    }
+
+    #[test]
+    fn degenerate_cameras_are_rejected() {
+        assert!(Camera::default().validate().is_ok());
+        let looking_at_itself = Camera {
+            target: Vec3::ZERO,
+            ..Default::default()
+        };
+        assert!(looking_at_itself.validate().is_err());
+        let looking_up = Camera {
+            target: Vec3::new(0f32, 3f32, 0f32),
+            ..Default::default()
+        };
+        assert!(looking_up.validate().is_err());
+        let squashed = Camera {
+            aspect: Some(0f32),
+            ..Default::default()
+        };
+        assert!(squashed.validate().is_err());
+    }
+
+    fn unit_sphere() -> Arc<Mesh> {
+        let sphere: Box<dyn Traceable> = Box::new(Sphere {
+            center: Vec3::ZERO,
+            radius: 1f32,
+            material: Material::IVORY,
+        });
+        return Arc::new(Mesh::new(vec![sphere]).unwrap());
+    }
+
+    #[test]
+    fn instances_match_the_transformed_object() {
+        let transform = Transform {
+            translation: Vec3::new(1f32, 2f32, -3f32),
+            rotation: Vec3::new(30f32, 45f32, 60f32),
+            scale: Vec3::splat(2f32),
+            matrix: None,
+        };
+        let instance = Instance::new(unit_sphere(), &transform).unwrap();
+        let sphere = Sphere {
+            center: transform.translation,
+            radius: 2f32,
+            material: Material::IVORY,
+        };
+
+        let origin = Vec3::new(0f32, 0f32, 5f32);
+        for offset in [Vec2::ZERO, Vec2::new(0.5, -1f32), Vec2::new(-1.2, 0.7)] {
+            let target = transform.translation + offset.extend(0f32);
+            let direction = (target - origin).normalize();
+            let expected = sphere.intersection(&origin, &direction).unwrap();
+            let actual = instance.intersection(&origin, &direction).unwrap();
+            assert!(actual.point.abs_diff_eq(expected.point, 1e-4));
+            assert!(actual.normal.abs_diff_eq(expected.normal, 1e-4));
+        }
+        assert!((instance.area() - sphere.area()).abs() < 1e-3);
+        let bounds = instance.bounding_box();
+        assert!(bounds.min.cmple(sphere.bounding_box().min).all());
+        assert!(bounds.max.cmpge(sphere.bounding_box().max).all());
+    }
+
+    #[test]
+    fn non_uniform_scaling_keeps_normals_perpendicular() {
+        let transform = Transform {
+            scale: Vec3::new(4f32, 1f32, 1f32),
+            ..Default::default()
+        };
+        let instance = Instance::new(unit_sphere(), &transform).unwrap();
+        // The ellipse x² / 16 + y² = 1 has the slope -x / (16 y) at every point.
+        let origin = Vec3::new(2f32, 5f32, 0f32);
+        let hit = instance.intersection(&origin, &Vec3::NEG_Y).unwrap();
+        assert!((hit.point.y - 0.75f32.sqrt()).abs() < 1e-4);
+        let expected = Vec3::new(hit.point.x / 16f32, hit.point.y, 0f32).normalize();
+        assert!(hit.normal.abs_diff_eq(expected, 1e-4));
+    }
+
+    #[test]
+    fn transforms_that_cannot_be_undone_are_rejected() {
+        let flattened = Transform {
+            scale: Vec3::new(1f32, 0f32, 1f32),
+            ..Default::default()
+        };
+        assert!(Instance::new(unit_sphere(), &flattened).is_err());
+        let projection = Transform {
+            matrix: Some([
+                [1f32, 0f32, 0f32, 0f32],
+                [0f32, 1f32, 0f32, 0f32],
+                [0f32, 0f32, 1f32, 0f32],
+                [0f32, 0f32, -1f32, 0f32],
+            ]),
+            ..Default::default()
+        };
+        assert!(Instance::new(unit_sphere(), &projection).is_err());
+        let mirrored = Transform {
+            scale: Vec3::new(-1f32, 0.01, 3f32),
+            ..Default::default()
+        };
+        assert!(Instance::new(unit_sphere(), &mirrored).is_ok());
+    }
+
+    #[test]
+    fn instances_sample_each_emissive_object_of_their_mesh() {
+        let glowing = Material::Phong(PhongMaterial {
+            emission: Some(Emission {
+                color: ColorInput::Constant(Color::WHITE),
+                strength: 1f32,
+            }),
+            ..match Material::IVORY {
+                Material::Phong(material) => material,
+                Material::Pbr(_) => unreachable!(),
+            }
+        });
+        let triangle = |x: f32, material: Material| -> Box<dyn Traceable> {
+            Box::new(Triangle {
+                v0: Vec3::new(x, 0f32, 0f32),
+                v1: Vec3::new(x + 1f32, 0f32, 0f32),
+                v2: Vec3::new(x, 1f32, 0f32),
+                normals: None,
+                uvs: None,
+                material,
+            })
+        };
+        // The first object is not emissive, so the mesh cannot be judged by it.
+        let mesh = Mesh::new(vec![
+            triangle(0f32, Material::IVORY),
+            triangle(2f32, glowing),
+        ]);
+        let transform = Transform {
+            scale: Vec3::new(2f32, 3f32, 1f32),
+            ..Default::default()
+        };
+        let instance = Instance::new(Arc::new(mesh.unwrap()), &transform).unwrap();
+        assert_eq!(instance.emitters(), vec![(1, 3f32)]);
+
+        let mut scene = Scene {
+            lights: Vec::new(),
+            objects: vec![Box::new(instance)],
+            background: None,
+            camera: Camera::default(),
+            bvh: None,
+            emitters: Emitters::default(),
+        };
+        scene.collect_emitters();
+        let point = Vec3::new(5f32, 1f32, 4f32);
+        for u in [Vec2::ZERO, Vec2::new(0.3, 0.8), Vec2::splat(0.99)] {
+            let sample = scene.sample_emitters(&point, u, 0.5).unwrap();
+            let target = point + sample.direction * sample.distance;
+            assert!(
+                target.x >= 4f32 - 1e-2 && target.x <= 6f32 + 1e-2,
+                "{target}"
+            );
+            assert_eq!(sample.radiance, Vec3::ONE);
+        }
+    }
+
+    #[test]
+    fn emitter_samples_measure_the_stretched_area() {
+        let transform = Transform {
+            scale: Vec3::new(4f32, 1f32, 1f32),
+            ..Default::default()
+        };
+        let instance = Instance::new(unit_sphere(), &transform).unwrap();
+        // Averaging the inverse densities over the sphere gives the area of the spheroid.
+        let steps = 200;
+        let mut total = 0f32;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u = Vec2::new(i as f32 + 0.5, j as f32 + 0.5) / steps as f32;
+                total += instance.sample_emitter(0, u).1;
+            }
+        }
+        let average = total / (steps * steps) as f32;
+        let eccentricity = (1f32 - 1f32 / 16f32).sqrt();
+        let expected = 2f32 * PI * (1f32 + 4f32 / eccentricity * eccentricity.asin());
+        assert!(
+            (average - expected).abs() < expected * 1e-3,
+            "{average} {expected}"
+        );
     }
 }
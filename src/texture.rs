@@ -0,0 +1,617 @@
+#![allow(clippy::needless_return)]
+
+use std::{f32::consts::PI, path::PathBuf, sync::Arc};
+
+use glam::{Vec2, Vec3};
+use image::Rgb32FImage;
+use serde::{
+    Deserialize, Deserializer, Serialize,
+    de::{DeserializeOwned, Error},
+};
+
+use crate::structures::{Color, Intersection};
+
+/// Distance over which the slope of procedural bump maps is measured.
+const BUMP_STEP: f32 = 1e-3;
+
+/// A material input that is either constant or looked up from a texture at the shaded point. In
+/// the scene JSON, textures are told apart from plain values by their `"type"`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum MaterialInput<T> {
+    Constant(T),
+    Texture(Texture),
+}
+
+pub type ColorInput = MaterialInput<Color>;
+pub type ScalarInput = MaterialInput<f32>;
+
+impl<T> MaterialInput<T> {
+    pub fn texture_mut(&mut self) -> Option<&mut Texture> {
+        return match self {
+            MaterialInput::Constant(_) => None,
+            MaterialInput::Texture(texture) => Some(texture),
+        };
+    }
+}
+
+impl ColorInput {
+    /// Linear color at the shaded point.
+    pub fn evaluate(&self, intersection: &Intersection) -> Vec3 {
+        return match self {
+            MaterialInput::Constant(color) => color.as_linear(),
+            MaterialInput::Texture(texture) => texture.evaluate(intersection),
+        };
+    }
+}
+
+impl ScalarInput {
+    /// Value at the shaded point. Textures are averaged over their channels.
+    pub fn evaluate(&self, intersection: &Intersection) -> f32 {
+        return match self {
+            MaterialInput::Constant(value) => *value,
+            MaterialInput::Texture(texture) => texture.evaluate(intersection).element_sum() / 3f32,
+        };
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for MaterialInput<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let input = match value.get("type") {
+            Some(_) => serde_path_to_error::deserialize(value).map(MaterialInput::Texture),
+            None => serde_path_to_error::deserialize(value).map(MaterialInput::Constant),
+        };
+        // Keeps the position of the problem within the texture in the message, since the path
+        // tracking of the scene loader cannot see through the buffered value.
+        return input.map_err(|error| match error.path().to_string().as_str() {
+            "." => D::Error::custom(error.into_inner()),
+            path => D::Error::custom(format!("{path}: {}", error.into_inner())),
+        });
+    }
+}
+
+/// How the data of an image texture is interpreted, which depends on the slot it is used in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureUsage {
+    /// sRGB encoded colors.
+    Color,
+    /// Linear values such as roughness.
+    Data,
+}
+
+/// Endpoint of a procedural texture: a color, or a number for scalar slots.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TextureValue {
+    Number(f32),
+    Color(Color),
+}
+
+impl TextureValue {
+    fn as_linear(&self) -> Vec3 {
+        return match self {
+            TextureValue::Number(value) => Vec3::splat(*value),
+            TextureValue::Color(color) => color.as_linear(),
+        };
+    }
+}
+
+fn default_texture_values() -> [TextureValue; 2] {
+    return [TextureValue::Number(0f32), TextureValue::Number(1f32)];
+}
+
+fn default_one() -> f32 {
+    return 1f32;
+}
+
+fn default_octaves() -> u32 {
+    return 6;
+}
+
+fn default_marble_strength() -> f32 {
+    return 5f32;
+}
+
+fn default_wood_axis() -> Vec3 {
+    return Vec3::Y;
+}
+
+/// Source of a value that varies over a surface, tagged by `"type"` in the scene JSON. The
+/// procedural textures are evaluated at the hit point in world space and blend between their two
+/// `values`, which default to 0 and 1.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Texture {
+    Image(ImageTexture),
+    /// Three dimensional checkerboard of cubes with edges of `size`.
+    Checker {
+        #[serde(default = "default_one")]
+        size: f32,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+    /// Perlin noise with features about `1 / scale` apart.
+    Noise {
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+    /// Sum of `octaves` layers of Perlin noise at doubling frequencies, for a cloudy look.
+    Turbulence {
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+    /// Veins along the x axis, `scale` per unit, distorted by turbulence of `strength`.
+    Marble {
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_marble_strength")]
+        strength: f32,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+    /// Growth rings around the line through `center` along `axis`, `scale` per unit, wobbled by
+    /// noise of `strength`.
+    Wood {
+        #[serde(default = "default_one")]
+        scale: f32,
+        #[serde(default)]
+        center: Vec3,
+        #[serde(default = "default_wood_axis")]
+        axis: Vec3,
+        #[serde(default = "default_one")]
+        strength: f32,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+    /// Linear blend from the first value at `start` to the second at `end`.
+    Gradient {
+        start: Vec3,
+        end: Vec3,
+        #[serde(default = "default_texture_values")]
+        values: [TextureValue; 2],
+    },
+}
+
+impl Texture {
+    pub fn evaluate(&self, intersection: &Intersection) -> Vec3 {
+        let point = intersection.point;
+        let (t, values) = match self {
+            Texture::Image(texture) => return texture.sample(intersection.uv),
+            Texture::Checker { size, values } => {
+                // Nudges points off the cell boundaries so that surfaces lying exactly on one,
+                // such as an axis aligned floor, do not flicker between two cells.
+                let cell = (point / *size + Vec3::splat(1e-3)).floor();
+                let parity = (cell.x + cell.y + cell.z).rem_euclid(2f32);
+                (parity, values)
+            }
+            Texture::Noise { scale, values } => (0.5 * (perlin(point * *scale) + 1f32), values),
+            Texture::Turbulence {
+                scale,
+                octaves,
+                values,
+            } => (turbulence(point * *scale, *octaves), values),
+            Texture::Marble {
+                scale,
+                octaves,
+                strength,
+                values,
+            } => {
+                let phase =
+                    2f32 * PI * *scale * point.x + *strength * turbulence(point * *scale, *octaves);
+                (0.5 * (1f32 + phase.sin()), values)
+            }
+            Texture::Wood {
+                scale,
+                center,
+                axis,
+                strength,
+                values,
+            } => {
+                let axis = axis.normalize_or(Vec3::Y);
+                let offset = point - *center;
+                let radius = (offset - axis * offset.dot(axis)).length();
+                (
+                    (*scale * radius + *strength * perlin(point)).rem_euclid(1f32),
+                    values,
+                )
+            }
+            Texture::Gradient { start, end, values } => {
+                let span = *end - *start;
+                let t = (point - *start).dot(span) / span.length_squared().max(f32::EPSILON);
+                (t, values)
+            }
+        };
+        let t = t.clamp(0f32, 1f32);
+        return values[0].as_linear().lerp(values[1].as_linear(), t);
+    }
+
+    /// Rate at which the average of the channels grows along `tangent` and `bitangent`, found by
+    /// central differences. Image textures measure it per pixel, and the others per unit of
+    /// distance.
+    fn slope(&self, intersection: &Intersection, tangent: &Vec3, bitangent: &Vec3) -> Vec2 {
+        let height = |point: Vec3, uv: Vec2| {
+            let shifted = Intersection {
+                point,
+                uv,
+                ..*intersection
+            };
+            self.evaluate(&shifted).element_sum() / 3f32
+        };
+        let (point, uv) = (intersection.point, intersection.uv);
+        let difference = |point_offset: Vec3, uv_offset: Vec2| {
+            height(point + point_offset, uv + uv_offset)
+                - height(point - point_offset, uv - uv_offset)
+        };
+
+        return match self {
+            Texture::Image(texture) => {
+                let size = texture.image.as_ref().map_or(Vec2::ONE, |image| {
+                    Vec2::new(image.width() as f32, image.height() as f32)
+                });
+                let texel = 1f32 / (size * texture.scale);
+                Vec2::new(
+                    difference(Vec3::ZERO, Vec2::new(texel.x, 0f32)),
+                    difference(Vec3::ZERO, Vec2::new(0f32, texel.y)),
+                ) / 2f32
+            }
+            _ => {
+                Vec2::new(
+                    difference(*tangent * BUMP_STEP, Vec2::ZERO),
+                    difference(*bitangent * BUMP_STEP, Vec2::ZERO),
+                ) / (2f32 * BUMP_STEP)
+            }
+        };
+    }
+}
+
+/// Ken Perlin's improved gradient noise, roughly in `[-1, 1]`. Lattice gradients come from
+/// hashing the cell coordinates instead of a permutation table.
+fn perlin(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let f = point - cell;
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let fade = f * f * f * (f * (f * 6f32 - Vec3::splat(15f32)) + Vec3::splat(10f32));
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let hash = lattice_hash(x + dx, y + dy, z + dz);
+        gradient(hash, f - Vec3::new(dx as f32, dy as f32, dz as f32))
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x);
+    return lerp(lerp(x00, x10, fade.y), lerp(x01, x11, fade.y), fade.z);
+}
+
+fn turbulence(point: Vec3, octaves: u32) -> f32 {
+    let mut sum = 0f32;
+    let mut frequency = 1f32;
+    for _ in 0..octaves {
+        sum += perlin(point * frequency).abs() / frequency;
+        frequency *= 2f32;
+    }
+    return sum;
+}
+
+fn lattice_hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7feb352d);
+    hash ^= hash >> 15;
+    return hash;
+}
+
+/// Dot product of `offset` with one of the twelve edge directions of a cube.
+fn gradient(hash: u32, offset: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { offset.x } else { offset.y };
+    let v = if h < 4 {
+        offset.y
+    } else if h == 12 || h == 14 {
+        offset.x
+    } else {
+        offset.z
+    };
+    return (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v });
+}
+
+/// Bends `normal` toward the direction stored in a tangent-space normal map.
+pub fn apply_normal_map(normal_map: &Texture, intersection: &Intersection, normal: &Vec3) -> Vec3 {
+    let (tangent, bitangent) = intersection.tangent_frame(normal);
+    let local = normal_map.evaluate(intersection) * 2f32 - Vec3::ONE;
+    return (tangent * local.x + bitangent * local.y + *normal * local.z)
+        .try_normalize()
+        .unwrap_or(*normal);
+}
+
+/// Height map that tilts the shading normal along its slope, so that the surface looks raised
+/// where the texture is bright.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BumpMap {
+    pub texture: Texture,
+    /// Scales the slope. Negative values turn bumps into dents.
+    #[serde(default = "default_one")]
+    pub strength: f32,
+}
+
+impl BumpMap {
+    pub fn apply(&self, intersection: &Intersection, normal: &Vec3) -> Vec3 {
+        let (tangent, bitangent) = intersection.tangent_frame(normal);
+        let slope = self.texture.slope(intersection, &tangent, &bitangent);
+        return (*normal - self.strength * (tangent * slope.x + bitangent * slope.y))
+            .try_normalize()
+            .unwrap_or(*normal);
+    }
+}
+
+/// How texture coordinates outside of `[0, 1]` are mapped onto the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapMode {
+    /// Tiles the image.
+    #[default]
+    Repeat,
+    /// Tiles the image, flipping every other copy so that edges line up.
+    Mirror,
+    /// Stretches the border pixels.
+    Clamp,
+}
+
+impl WrapMode {
+    /// Maps a pixel coordinate that may lie outside of the image onto a pixel within `size`.
+    fn apply(&self, coordinate: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => coordinate.rem_euclid(size),
+            WrapMode::Mirror => {
+                let position = coordinate.rem_euclid(2 * size);
+                match position < size {
+                    true => position,
+                    false => 2 * size - 1 - position,
+                }
+            }
+            WrapMode::Clamp => coordinate.clamp(0, size - 1),
+        };
+        return wrapped as u32;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    Nearest,
+    /// Blends the four pixels closest to the sample position.
+    #[default]
+    Bilinear,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ImageTexture {
+    /// Image file, relative to the scene file.
+    pub path: PathBuf,
+    /// Wrap modes along u and v. The scene JSON takes one mode for both or a pair.
+    #[serde(default, deserialize_with = "deserialize_wrap")]
+    pub wrap: [WrapMode; 2],
+    #[serde(default)]
+    pub filter: TextureFilter,
+    /// Number of times the image is repeated across the texture coordinates along each axis.
+    #[serde(default = "default_texture_scale")]
+    pub scale: Vec2,
+    /// Pixel data, filled in once the scene is loaded. Textures that were never loaded evaluate
+    /// to white.
+    #[serde(skip)]
+    pub image: Option<Arc<Rgb32FImage>>,
+}
+
+fn default_texture_scale() -> Vec2 {
+    return Vec2::ONE;
+}
+
+fn deserialize_wrap<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[WrapMode; 2], D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a wrap mode or a pair of them")]
+    enum Wrap {
+        Both(WrapMode),
+        PerAxis([WrapMode; 2]),
+    }
+    return match Wrap::deserialize(deserializer)? {
+        Wrap::Both(wrap) => Ok([wrap; 2]),
+        Wrap::PerAxis(wrap) => Ok(wrap),
+    };
+}
+
+impl ImageTexture {
+    /// Linear color at texture coordinates `uv`, with `(0, 0)` at the bottom left of the image.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let Some(image) = &self.image else {
+            return Vec3::ONE;
+        };
+        let (width, height) = image.dimensions();
+        let position = Vec2::new(uv.x * self.scale.x, 1f32 - uv.y * self.scale.y)
+            * Vec2::new(width as f32, height as f32);
+        let texel = |x: i64, y: i64| {
+            let pixel =
+                image.get_pixel(self.wrap[0].apply(x, width), self.wrap[1].apply(y, height));
+            Vec3::from_array(pixel.0)
+        };
+
+        return match self.filter {
+            TextureFilter::Nearest => texel(position.x.floor() as i64, position.y.floor() as i64),
+            TextureFilter::Bilinear => {
+                // Pixel centers sit at half coordinates.
+                let position = position - Vec2::splat(0.5);
+                let corner = position.floor();
+                let t = position - corner;
+                let (x, y) = (corner.x as i64, corner.y as i64);
+                let top = texel(x, y).lerp(texel(x + 1, y), t.x);
+                let bottom = texel(x, y + 1).lerp(texel(x + 1, y + 1), t.x);
+                top.lerp(bottom, t.y)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::{Material, Traceable, Triangle};
+    use glam::Vec4;
+    use image::Rgb;
+
+    fn evaluate_at(texture: &Texture, point: Vec3) -> Vec3 {
+        let intersection = Intersection {
+            point,
+            normal: Vec3::Y,
+            uv: Vec2::ZERO,
+            tangent: Vec4::X,
+            material: &Material::IVORY,
+        };
+        return texture.evaluate(&intersection);
+    }
+
+    #[test]
+    fn procedural_textures_blend_between_their_values() {
+        let values = [TextureValue::Number(0.25), TextureValue::Number(0.75)];
+        let checker = Texture::Checker { size: 2f32, values };
+        // A floor lying exactly on a cell boundary must not flicker between cells.
+        assert_eq!(
+            evaluate_at(&checker, Vec3::new(0.5, -2f32, 0.5)),
+            Vec3::splat(0.75)
+        );
+        assert_eq!(
+            evaluate_at(&checker, Vec3::new(0.5, -2.00001, 0.5)),
+            Vec3::splat(0.75)
+        );
+        assert_eq!(
+            evaluate_at(&checker, Vec3::new(2.5, -2f32, 0.5)),
+            Vec3::splat(0.25)
+        );
+
+        let gradient = Texture::Gradient {
+            start: Vec3::ZERO,
+            end: Vec3::new(0f32, 0f32, 4f32),
+            values,
+        };
+        assert_eq!(
+            evaluate_at(&gradient, Vec3::new(1f32, 5f32, -1f32)),
+            Vec3::splat(0.25)
+        );
+        assert_eq!(
+            evaluate_at(&gradient, Vec3::new(0f32, 0f32, 2f32)),
+            Vec3::splat(0.5)
+        );
+        assert_eq!(
+            evaluate_at(&gradient, Vec3::new(0f32, 0f32, 9f32)),
+            Vec3::splat(0.75)
+        );
+
+        let noise = Texture::Noise {
+            scale: 3f32,
+            values,
+        };
+        for i in 0..100 {
+            let point = Vec3::new(i as f32 * 0.37, i as f32 * -0.11, i as f32 * 0.53);
+            let value = evaluate_at(&noise, point).x;
+            assert!((0.25..=0.75).contains(&value), "{value}");
+        }
+    }
+
+    #[test]
+    fn wrap_modes_map_outside_coordinates_into_the_image() {
+        let coordinates = [-5, -1, 0, 3, 4, 7];
+        let wrapped = |mode: WrapMode| coordinates.map(|coordinate| mode.apply(coordinate, 4));
+
+        assert_eq!(wrapped(WrapMode::Repeat), [3, 3, 0, 3, 0, 3]);
+        assert_eq!(wrapped(WrapMode::Mirror), [3, 0, 0, 3, 3, 0]);
+        assert_eq!(wrapped(WrapMode::Clamp), [0, 0, 0, 3, 3, 3]);
+    }
+
+    #[test]
+    fn wrap_modes_are_given_for_both_axes_or_each_one() {
+        let wrap = |wrap: serde_json::Value| {
+            let texture: ImageTexture =
+                serde_json::from_value(serde_json::json!({ "path": "bricks.png", "wrap": wrap }))
+                    .unwrap();
+            texture.wrap
+        };
+
+        assert_eq!(wrap(serde_json::json!("mirror")), [WrapMode::Mirror; 2]);
+        assert_eq!(
+            wrap(serde_json::json!(["clamp", "repeat"])),
+            [WrapMode::Clamp, WrapMode::Repeat]
+        );
+    }
+
+    #[test]
+    fn bilinear_filtering_blends_neighbouring_pixels() {
+        // Black on the left, white on the right.
+        let image = Rgb32FImage::from_fn(2, 2, |x, _| Rgb([x as f32; 3]));
+        let texture = ImageTexture {
+            path: PathBuf::new(),
+            wrap: [WrapMode::Clamp; 2],
+            filter: TextureFilter::Bilinear,
+            scale: Vec2::ONE,
+            image: Some(Arc::new(image)),
+        };
+
+        assert_eq!(texture.sample(Vec2::new(0.25, 0.5)), Vec3::ZERO);
+        assert_eq!(texture.sample(Vec2::new(0.5, 0.5)), Vec3::splat(0.5));
+        assert_eq!(texture.sample(Vec2::new(0.75, 0.5)), Vec3::ONE);
+    }
+
+    #[test]
+    fn normal_perturbations_follow_the_texture_coordinates() {
+        // Facing +z, with u growing along +y and v along +x, which mirrors the usual frame.
+        let triangle = Triangle {
+            v0: Vec3::ZERO,
+            v1: Vec3::X,
+            v2: Vec3::Y,
+            normals: None,
+            uvs: Some([Vec2::ZERO, Vec2::Y, Vec2::X]),
+            material: Material::IVORY,
+        };
+        let intersection = triangle
+            .intersection(&Vec3::new(0.2, 0.2, 1f32), &Vec3::NEG_Z)
+            .unwrap();
+        let normal = intersection.normal;
+        assert_eq!(intersection.tangent_frame(&normal), (Vec3::Y, Vec3::X));
+
+        let image = Rgb32FImage::from_pixel(1, 1, Rgb([1f32, 0.5, 0.5]));
+        let normal_map = Texture::Image(ImageTexture {
+            path: PathBuf::new(),
+            wrap: [WrapMode::Repeat; 2],
+            filter: TextureFilter::Nearest,
+            scale: Vec2::ONE,
+            image: Some(Arc::new(image)),
+        });
+        assert_eq!(
+            apply_normal_map(&normal_map, &intersection, &normal),
+            Vec3::Y
+        );
+
+        // Height rising toward +x tilts the normal toward -x.
+        let bump_map = BumpMap {
+            texture: Texture::Gradient {
+                start: Vec3::ZERO,
+                end: Vec3::X,
+                values: default_texture_values(),
+            },
+            strength: 1f32,
+        };
+        let bumped = bump_map.apply(&intersection, &normal);
+        let expected = Vec3::new(-1f32, 0f32, 1f32).normalize();
+        assert!((bumped - expected).length() < 1e-3, "{bumped}");
+    }
+}